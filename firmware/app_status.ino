@@ -0,0 +1,238 @@
+// Firmware for a Particle Photon with an Internet Button, implementing version 6 of the protocol
+// described in PROTOCOL.md. Flash it with the Particle CLI or Web IDE after adding the
+// InternetButton library:
+//
+//     particle library add InternetButton
+//     particle flash <device> firmware/
+//
+// Keep PROTOCOL_VERSION in step with PROTOCOL_VERSION in src/indicators/particle.rs.
+
+#include "InternetButton.h"
+
+#define PROTOCOL_VERSION 6
+#define FIRST_LED 1
+#define LED_COUNT 11
+#define BLINK_INTERVAL 500
+#define PULSE_PERIOD 2000
+#define RAINBOW_PERIOD 3000
+// A flash is three quick blinks.
+#define FLASH_INTERVAL 150
+#define FLASH_DURATION (6 * FLASH_INTERVAL)
+#define BUTTON_COUNT 4
+
+InternetButton button = InternetButton();
+
+int protocolVersion = PROTOCOL_VERSION;
+
+struct Color {
+    uint8_t red;
+    uint8_t green;
+    uint8_t blue;
+};
+
+enum Pattern { SOLID, BLINK, PULSE, RAINBOW };
+
+const Color OFF = {0, 0, 0};
+
+Color colors[LED_COUNT];
+Pattern patterns[LED_COUNT];
+// Whether an LED blinks until the alerts are acknowledged, whatever its pattern.
+bool alerting[LED_COUNT];
+// When an LED started flashing, or 0 when it isn't.
+unsigned long flashStart[LED_COUNT];
+bool pressed[BUTTON_COUNT];
+unsigned long lastFrame = 0;
+
+// Sets the LED in "<led>[+flash][+alert]" and returns its number, or -1 when it isn't an LED on
+// the button.
+int showLed(String arg, Color color, Pattern pattern) {
+    int led = arg.toInt();
+    if (led < FIRST_LED || led >= FIRST_LED + LED_COUNT) {
+        return -1;
+    }
+    int i = led - FIRST_LED;
+    colors[i] = color;
+    patterns[i] = pattern;
+    alerting[i] = arg.indexOf("+alert") >= 0;
+    flashStart[i] = arg.indexOf("+flash") >= 0 ? max(millis(), 1UL) : 0;
+    button.ledOn(led, color.red, color.green, color.blue);
+    return led;
+}
+
+int setOnline(String arg) { return showLed(arg, {0, 255, 0}, SOLID); }
+int setOffline(String arg) { return showLed(arg, {255, 0, 0}, SOLID); }
+int setFailed(String arg) { return showLed(arg, {255, 0, 0}, SOLID); }
+int setActivating(String arg) { return showLed(arg, {255, 160, 0}, SOLID); }
+int setDeactivating(String arg) { return showLed(arg, {255, 80, 0}, SOLID); }
+int setReloading(String arg) { return showLed(arg, {0, 160, 255}, SOLID); }
+int setMaintenance(String arg) { return showLed(arg, {160, 0, 255}, SOLID); }
+int setUnreachable(String arg) { return showLed(arg, {255, 0, 160}, SOLID); }
+int setLost(String arg) { return showLed(arg, {255, 0, 160}, PULSE); }
+int setUndefined(String arg) { return showLed(arg, {40, 40, 40}, SOLID); }
+
+// Sets an LED from "<led>:<rrggbb>:<pattern>[+flash][+alert]", returning the LED or a negative
+// number.
+int setLed(String arg) {
+    int first = arg.indexOf(':');
+    int second = arg.indexOf(':', first + 1);
+    if (first < 0 || second < 0 || second - first != 7) {
+        return -1;
+    }
+    long rgb = strtol(arg.substring(first + 1, second).c_str(), NULL, 16);
+    Color color = {(uint8_t)(rgb >> 16), (uint8_t)(rgb >> 8), (uint8_t)rgb};
+
+    int plus = arg.indexOf('+', second);
+    String name = plus < 0 ? arg.substring(second + 1) : arg.substring(second + 1, plus);
+    String flags = plus < 0 ? String("") : arg.substring(plus);
+    Pattern pattern;
+    if (name == "solid") pattern = SOLID;
+    else if (name == "blink") pattern = BLINK;
+    else if (name == "pulse") pattern = PULSE;
+    else if (name == "rainbow") pattern = RAINBOW;
+    else return -2;
+
+    return showLed(arg.substring(0, first) + flags, color, pattern);
+}
+
+// Sets the LED in a "<led>:<status>" pair or a "<led>:<rrggbb>:<pattern>" entry, either with
+// optional flags, returning the LED or a negative number.
+int setPair(String pair) {
+    int colon = pair.indexOf(':');
+    if (colon < 0) {
+        return -1;
+    }
+    if (pair.indexOf(':', colon + 1) >= 0) {
+        return setLed(pair);
+    }
+
+    int plus = pair.indexOf('+', colon);
+    String status = plus < 0 ? pair.substring(colon + 1) : pair.substring(colon + 1, plus);
+    String led = pair.substring(0, colon) + (plus < 0 ? String("") : pair.substring(plus));
+
+    if (status == "online") return setOnline(led);
+    if (status == "offline" || status == "errored") return setOffline(led);
+    if (status == "failed") return setFailed(led);
+    if (status == "activating") return setActivating(led);
+    if (status == "deactivating") return setDeactivating(led);
+    if (status == "reloading") return setReloading(led);
+    if (status == "maintenance") return setMaintenance(led);
+    if (status == "unreachable") return setUnreachable(led);
+    if (status == "lost") return setLost(led);
+    if (status == "unknown") return setUndefined(led);
+    return -2;
+}
+
+// Sets every entry in a comma separated list and returns how many were set. Stops at the first bad
+// entry, returning its error.
+int setLeds(String arg) {
+    int count = 0;
+    int start = 0;
+    while (start < (int)arg.length()) {
+        int end = arg.indexOf(',', start);
+        if (end < 0) {
+            end = arg.length();
+        }
+        int result = setPair(arg.substring(start, end));
+        if (result < 0) {
+            return result;
+        }
+        count++;
+        start = end + 1;
+    }
+    return count;
+}
+
+void setup() {
+    button.begin();
+    for (int i = 0; i < LED_COUNT; i++) {
+        colors[i] = OFF;
+        patterns[i] = SOLID;
+        alerting[i] = false;
+        flashStart[i] = 0;
+    }
+
+    Particle.variable("protocolVersion", protocolVersion);
+    Particle.function("setOnline", setOnline);
+    Particle.function("setOffline", setOffline);
+    Particle.function("setFailed", setFailed);
+    Particle.function("setActivating", setActivating);
+    Particle.function("setDeactivating", setDeactivating);
+    Particle.function("setReloading", setReloading);
+    Particle.function("setMaintenance", setMaintenance);
+    Particle.function("setUnreachable", setUnreachable);
+    Particle.function("setLost", setLost);
+    Particle.function("setUndefined", setUndefined);
+    Particle.function("setLed", setLed);
+    Particle.function("setLeds", setLeds);
+}
+
+// Scales `color` by `level` out of 255.
+Color dim(Color color, int level) {
+    return {(uint8_t)(color.red * level / 255), (uint8_t)(color.green * level / 255),
+            (uint8_t)(color.blue * level / 255)};
+}
+
+// A fully saturated color `position` out of 256 of the way around the color wheel.
+Color wheel(int position) {
+    position = position % 256;
+    if (position < 85) return {(uint8_t)(255 - position * 3), (uint8_t)(position * 3), 0};
+    if (position < 170) {
+        position -= 85;
+        return {0, (uint8_t)(255 - position * 3), (uint8_t)(position * 3)};
+    }
+    position -= 170;
+    return {(uint8_t)(position * 3), 0, (uint8_t)(255 - position * 3)};
+}
+
+// Publishes the number of a button when it goes down. What it does is up to the host.
+void readButtons() {
+    for (int i = 0; i < BUTTON_COUNT; i++) {
+        bool down = button.buttonOn(i + 1);
+        if (down && !pressed[i]) {
+            Particle.publish("app-status/button", String(i + 1), PRIVATE);
+        }
+        pressed[i] = down;
+    }
+}
+
+void loop() {
+    // Animates about 30 times a second.
+    unsigned long now = millis();
+    if (now - lastFrame < 33) {
+        return;
+    }
+    lastFrame = now;
+    readButtons();
+
+    bool blinkOn = (now / BLINK_INTERVAL) % 2 == 0;
+    int pulse = now % PULSE_PERIOD;
+    int pulseLevel = pulse < PULSE_PERIOD / 2 ? pulse * 510 / PULSE_PERIOD
+                                              : 510 - pulse * 510 / PULSE_PERIOD;
+
+    for (int i = 0; i < LED_COUNT; i++) {
+        Color color;
+        if (flashStart[i] != 0 && now - flashStart[i] < FLASH_DURATION) {
+            color = ((now - flashStart[i]) / FLASH_INTERVAL) % 2 == 0 ? colors[i] : OFF;
+        } else if (alerting[i]) {
+            flashStart[i] = 0;
+            color = blinkOn ? colors[i] : OFF;
+        } else {
+            flashStart[i] = 0;
+            switch (patterns[i]) {
+                case SOLID:
+                    color = colors[i];
+                    break;
+                case BLINK:
+                    color = blinkOn ? colors[i] : OFF;
+                    break;
+                case PULSE:
+                    color = dim(colors[i], pulseLevel);
+                    break;
+                case RAINBOW:
+                    color = wheel((now % RAINBOW_PERIOD) * 256 / RAINBOW_PERIOD + i * 256 / LED_COUNT);
+                    break;
+            }
+        }
+        button.ledOn(i + FIRST_LED, color.red, color.green, color.blue);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::backoff::Backoff;
+use crate::config::AgentConfig;
+use crate::poller::Poller;
+use crate::server::{StatusReport, REPORT_PATH};
+use crate::{Event, Status};
+use reqwest::blocking::Client;
+use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// How long the server may take to take a report.
+const REPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Polls the services like the daemon does, but reports them to the server instead of showing
+/// them: as soon as anything changes, and every heartbeat otherwise. Never returns.
+pub fn run(config: &AgentConfig, mut poller: Poller, receiver: Receiver<Event>) -> ! {
+    let client = Client::builder()
+        .user_agent(crate::USER_AGENT)
+        .timeout(REPORT_TIMEOUT)
+        .build()
+        .unwrap_or_else(|error| {
+            eprintln!("Could not set up an HTTP client: {}", error);
+            std::process::exit(1);
+        });
+    let url = format!("{}{}", config.server(), REPORT_PATH);
+    let mut reported: Option<HashMap<String, Status>> = None;
+    let mut next_heartbeat = Instant::now();
+    let mut backoff = Backoff::default();
+
+    loop {
+        let statuses: HashMap<String, Status> = poller.get_statuses().into_iter().collect();
+        let due = reported.as_ref() != Some(&statuses) || Instant::now() >= next_heartbeat;
+        if due && backoff.is_due() {
+            let report = StatusReport {
+                agent: config.name.clone(),
+                statuses,
+            };
+            let result = client
+                .post(&url)
+                .bearer_auth(&config.token)
+                .json(&report)
+                .send()
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => {
+                    if reported.is_none() {
+                        log!("Reporting to {}", config.server());
+                    }
+                    reported = Some(report.statuses);
+                    next_heartbeat = Instant::now() + config.heartbeat();
+                    backoff.reset();
+                }
+                Err(error) => {
+                    let delay = backoff.fail();
+                    log!(
+                        "Could not report to {}, retrying in {:.1}s: {}",
+                        config.server(),
+                        delay.as_secs_f32(),
+                        error
+                    );
+                    // Whatever the server last got is stale now, so the retry sends everything.
+                    reported = None;
+                }
+            }
+        }
+
+        let until_report = backoff
+            .until_due()
+            .unwrap_or_else(|| next_heartbeat.saturating_duration_since(Instant::now()));
+        let timeout = poller.until_next_poll().min(until_report);
+        match receiver.recv_timeout(timeout) {
+            Ok(event) => {
+                for event in std::iter::once(event).chain(receiver.try_iter()) {
+                    if let Event::ServiceChanged(id) = &event {
+                        poller.poll_now(id);
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Spaces out retries exponentially after consecutive failures, with jitter so LEDs that failed
+/// together don't all retry at the same moment.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn is_due(&self) -> bool {
+        self.next_attempt
+            .is_none_or(|next_attempt| Instant::now() >= next_attempt)
+    }
+
+    /// How long until the next retry, if one is waiting.
+    pub fn until_due(&self) -> Option<Duration> {
+        self.next_attempt
+            .map(|next_attempt| next_attempt.saturating_duration_since(Instant::now()))
+    }
+
+    /// Records a failure and returns how long to wait before trying again.
+    pub fn fail(&mut self) -> Duration {
+        let ceiling = INITIAL_DELAY
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(MAX_DELAY);
+        // Waits somewhere between half and all of the ceiling.
+        let delay = ceiling.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+
+        self.failures = self.failures.saturating_add(1);
+        self.next_attempt = Some(Instant::now() + delay);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.next_attempt = None;
+    }
+}
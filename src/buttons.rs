@@ -0,0 +1,104 @@
+use crate::config::{ButtonAction, ButtonConfig};
+use crate::App;
+use std::time::{Duration, Instant};
+
+/// Acts on presses of the device's buttons: acknowledging alerts, stepping through the services
+/// one at a time, and muting alerts for a while.
+pub struct Buttons {
+    config: ButtonConfig,
+    muted_until: Option<Instant>,
+    /// The LED of the service last stepped to, if any.
+    detail: Option<u16>,
+}
+
+impl Buttons {
+    pub fn new(config: &ButtonConfig) -> Buttons {
+        Buttons {
+            config: config.clone(),
+            muted_until: None,
+            detail: None,
+        }
+    }
+
+    pub fn press(&mut self, button: u8, apps: Vec<&mut App>) {
+        match self.config.action(button) {
+            Some(ButtonAction::Acknowledge) => acknowledge(apps),
+            Some(ButtonAction::Detail) => self.step_detail(apps),
+            Some(ButtonAction::Mute) => {
+                if self.muted_until.take().is_some() {
+                    log!("Unmuting alerts");
+                } else {
+                    let duration = self.config.mute_duration();
+                    log!("Muting alerts for {}s", duration.as_secs());
+                    self.muted_until = Some(Instant::now() + duration);
+                }
+            }
+            None => log!("Button {} does nothing", button),
+        }
+    }
+
+    /// Mutes or unmutes `apps` to match whether alerts are muted right now, so the LEDs that are
+    /// alerting get updated.
+    pub fn apply_mute<'a>(&mut self, apps: impl Iterator<Item = &'a mut App>) {
+        if self
+            .muted_until
+            .is_some_and(|muted_until| Instant::now() >= muted_until)
+        {
+            log!("Alerts are no longer muted");
+            self.muted_until = None;
+        }
+
+        let muted = self.muted_until.is_some();
+        for app in apps.filter(|app| app.muted != muted) {
+            app.muted = muted;
+            if app.alerting {
+                app.confirmed_status = None;
+            }
+        }
+    }
+
+    /// How long until alerts are unmuted, if they are muted.
+    pub fn until_unmuted(&self) -> Option<Duration> {
+        self.muted_until
+            .map(|muted_until| muted_until.saturating_duration_since(Instant::now()))
+    }
+
+    /// Flashes the LED of the next service and logs what it shows. After the last service, stops.
+    fn step_detail(&mut self, mut apps: Vec<&mut App>) {
+        apps.retain(|app| app.led_num.is_some());
+        apps.sort_by_key(|app| app.led_num);
+
+        let next = apps
+            .into_iter()
+            .find(|app| self.detail.is_none_or(|detail| app.led_num > Some(detail)));
+        match next {
+            Some(app) => {
+                self.detail = app.led_num;
+                log!(
+                    "LED {} shows {} as {}",
+                    app.led_num.unwrap_or_default(),
+                    app.name,
+                    app.last_status.as_str()
+                );
+                app.flash = true;
+                app.confirmed_status = None;
+            }
+            None => {
+                log!("Done going through the LEDs");
+                self.detail = None;
+            }
+        }
+    }
+}
+
+fn acknowledge(apps: Vec<&mut App>) {
+    for app in apps.into_iter().filter(|app| app.alerting) {
+        log!(
+            "Acknowledged {} being {}",
+            app.name,
+            app.last_status.as_str()
+        );
+        app.alerting = false;
+        app.confirmed_status = None;
+    }
+}
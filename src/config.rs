@@ -0,0 +1,1388 @@
+use crate::systemd::{full_unit_name, glob_match};
+use crate::Status;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Used when neither the command line nor `APP_STATUS_CONFIG` name a config file.
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+/// Where LED assignments of unpinned services are remembered by default.
+pub const DEFAULT_STATE_FILE: &str = "led_state.toml";
+/// Seconds a health check may take when the service doesn't set a `timeout`.
+const DEFAULT_CHECK_TIMEOUT: u64 = 5;
+const DEFAULT_CONNECT_TIMEOUT: u64 = 5;
+const DEFAULT_RESYNC_INTERVAL: u64 = 600;
+const DEFAULT_MUTE_DURATION: u64 = 3600;
+/// Seconds a remote host gets to answer over SSH, including setting up the connection.
+const DEFAULT_HOST_TIMEOUT: u64 = 10;
+/// Seconds between an agent's reports when nothing changes.
+const DEFAULT_HEARTBEAT: u64 = 10;
+/// Seconds without a report before an agent counts as lost, a few missed heartbeats.
+const DEFAULT_AGENT_TIMEOUT: u64 = 30;
+/// The Internet Button has four buttons.
+const BUTTON_COUNT: u8 = 4;
+/// Function calls wait for the device to answer, which can take a while over a bad connection.
+const DEFAULT_PARTICLE_TIMEOUT: u64 = 20;
+const DEFAULT_PARTICLE_API_URL: &str = "https://api.particle.io";
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The only device, for configs that drive a single one. Moved into `devices` on load.
+    device: Option<DeviceConfig>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+    /// Seconds between polls for services that don't set their own `poll_interval`.
+    pub poll_interval: Option<u64>,
+    /// Seconds between sending every LED to the device again, in case it lost them.
+    pub resync_interval: Option<u64>,
+    /// Remembers which LED each unpinned service was given, so it keeps it across restarts.
+    pub state_file: Option<PathBuf>,
+    /// The default for devices that don't have an `overflow` section of their own.
+    #[serde(default)]
+    pub overflow: OverflowConfig,
+    /// The default for devices that don't have an `indicator` section of their own.
+    #[serde(default)]
+    pub indicator: IndicatorConfig,
+    #[serde(default)]
+    pub particle: ParticleConfig,
+    /// How statuses look on the device, overriding the firmware's defaults.
+    #[serde(default)]
+    pub styles: HashMap<Status, StyleConfig>,
+    #[serde(default)]
+    pub alerts: AlertConfig,
+    #[serde(default)]
+    pub buttons: ButtonConfig,
+    /// Remote machines whose systemd units are checked over SSH, by name.
+    #[serde(default)]
+    pub hosts: HashMap<String, HostConfig>,
+    /// Where this machine reports its services, in agent mode.
+    pub agent: Option<AgentConfig>,
+    /// Where agents report to, in server mode.
+    pub server: Option<ServerConfig>,
+    #[serde(default)]
+    pub services: Vec<ServiceConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    pub name: String,
+    /// Falls back to the `ACCESS_TOKEN` environment variable, so the token can stay out of the file.
+    pub access_token: Option<String>,
+    /// The LED hardware on the device, which sets defaults for `first_led` and `led_count`.
+    #[serde(default)]
+    pub profile: DeviceProfile,
+    pub first_led: Option<u16>,
+    pub led_count: Option<u16>,
+    /// LEDs that are never handed out automatically, though services can still pin them.
+    #[serde(default)]
+    pub reserved: Vec<u16>,
+    pub indicator: Option<IndicatorConfig>,
+    pub overflow: Option<OverflowConfig>,
+    /// Where this device's LED assignments are remembered, when there are several devices.
+    pub state_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceProfile {
+    /// The Internet Button's ring of 11 LEDs, numbered from 1.
+    #[default]
+    InternetButton,
+    /// A 24 pixel NeoPixel ring, numbered from 0.
+    #[serde(rename = "neopixel-24")]
+    Neopixel24,
+    /// A 60 pixel NeoPixel strip, numbered from 0.
+    #[serde(rename = "neopixel-60")]
+    Neopixel60,
+    /// Anything else; `led_count` has to be given.
+    Custom,
+}
+
+impl DeviceProfile {
+    fn first_led(self) -> u16 {
+        match self {
+            DeviceProfile::InternetButton => 1,
+            DeviceProfile::Neopixel24 | DeviceProfile::Neopixel60 | DeviceProfile::Custom => 0,
+        }
+    }
+
+    fn led_count(self) -> Option<u16> {
+        match self {
+            DeviceProfile::InternetButton => Some(11),
+            DeviceProfile::Neopixel24 => Some(24),
+            DeviceProfile::Neopixel60 => Some(60),
+            DeviceProfile::Custom => None,
+        }
+    }
+}
+
+/// Where statuses are shown.
+#[derive(Deserialize, Debug, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum IndicatorConfig {
+    /// Calls functions on `device.name` through the Particle Cloud.
+    #[default]
+    Particle,
+    /// Writes `<led>:<status>` lines to a serial-attached LED controller.
+    Serial { path: PathBuf, baud: Option<u32> },
+    /// Draws the LEDs on the terminal.
+    Terminal,
+    /// Drives LEDs under `/sys/class/leds`, mapping LED numbers to LED names or paths.
+    Sysfs {
+        leds: HashMap<String, String>,
+        /// Statuses that make the LED blink rather than turn off.
+        #[serde(default = "default_blink")]
+        blink: Vec<Status>,
+    },
+    /// POSTs every change as JSON to `url`.
+    Webhook { url: String },
+}
+
+fn default_blink() -> Vec<Status> {
+    vec![Status::Failed]
+}
+
+/// How the Particle Cloud API is called.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ParticleConfig {
+    /// Seconds to wait for a connection to the API.
+    pub connect_timeout: Option<u64>,
+    /// Seconds a whole call may take, including the device running the function.
+    pub timeout: Option<u64>,
+    /// Where the Particle Cloud API is, for a self-hosted cloud, a proxy or a mock.
+    pub api_url: Option<String>,
+}
+
+impl ParticleConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_PARTICLE_TIMEOUT))
+    }
+
+    /// The API's base URL, without a trailing slash.
+    pub fn api_url(&self) -> &str {
+        self.api_url
+            .as_deref()
+            .unwrap_or(DEFAULT_PARTICLE_API_URL)
+            .trim_end_matches('/')
+    }
+}
+
+/// The color and pattern a status is shown with, sent to the firmware's `setLed`.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct StyleConfig {
+    pub color: Color,
+    #[serde(default)]
+    pub pattern: Pattern,
+}
+
+/// An RGB color, written as `"#ff8000"` or `[255, 128, 0]`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "ColorConfig")]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    /// The color as `rrggbb`, the way the firmware takes it.
+    pub fn hex(&self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorConfig {
+    Hex(String),
+    Rgb([u8; 3]),
+}
+
+impl TryFrom<ColorConfig> for Color {
+    type Error = String;
+
+    fn try_from(config: ColorConfig) -> Result<Color, String> {
+        match config {
+            ColorConfig::Rgb([red, green, blue]) => Ok(Color(red, green, blue)),
+            ColorConfig::Hex(hex) => {
+                let digits = hex.strip_prefix('#').unwrap_or(&hex);
+                let value = u32::from_str_radix(digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() == 6)
+                    .ok_or_else(|| format!("{:?} is not a color like \"#ff8000\"", hex))?;
+                Ok(Color((value >> 16) as u8, (value >> 8) as u8, value as u8))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    #[default]
+    Solid,
+    Blink,
+    /// Fades in and out.
+    Pulse,
+    /// Cycles through every color, ignoring the configured one.
+    Rainbow,
+}
+
+impl Pattern {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Pattern::Solid => "solid",
+            Pattern::Blink => "blink",
+            Pattern::Pulse => "pulse",
+            Pattern::Rainbow => "rainbow",
+        }
+    }
+}
+
+/// How status changes are brought to people's attention.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    /// Flashes an LED a few times when its status changes, before it settles.
+    #[serde(default = "default_flash")]
+    pub flash: bool,
+    /// Statuses that keep blinking until acknowledged.
+    #[serde(default = "default_blink")]
+    pub statuses: Vec<Status>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> AlertConfig {
+        AlertConfig {
+            flash: default_flash(),
+            statuses: default_blink(),
+        }
+    }
+}
+
+fn default_flash() -> bool {
+    true
+}
+
+/// What the device's buttons, numbered from 1, do.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonConfig {
+    #[serde(default = "default_acknowledge_button")]
+    pub acknowledge: u8,
+    #[serde(default = "default_detail_button")]
+    pub detail: u8,
+    #[serde(default = "default_mute_button")]
+    pub mute: u8,
+    /// Seconds alerts stay muted for.
+    pub mute_duration: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonAction {
+    /// Stops the alerts blinking.
+    Acknowledge,
+    /// Highlights the next service and logs its details.
+    Detail,
+    /// Mutes alerts for `mute_duration`, or unmutes them.
+    Mute,
+}
+
+impl Default for ButtonConfig {
+    fn default() -> ButtonConfig {
+        ButtonConfig {
+            acknowledge: default_acknowledge_button(),
+            detail: default_detail_button(),
+            mute: default_mute_button(),
+            mute_duration: None,
+        }
+    }
+}
+
+impl ButtonConfig {
+    pub fn action(&self, button: u8) -> Option<ButtonAction> {
+        if button == self.acknowledge {
+            Some(ButtonAction::Acknowledge)
+        } else if button == self.detail {
+            Some(ButtonAction::Detail)
+        } else if button == self.mute {
+            Some(ButtonAction::Mute)
+        } else {
+            None
+        }
+    }
+
+    pub fn mute_duration(&self) -> Duration {
+        Duration::from_secs(self.mute_duration.unwrap_or(DEFAULT_MUTE_DURATION))
+    }
+}
+
+fn default_acknowledge_button() -> u8 {
+    1
+}
+
+fn default_detail_button() -> u8 {
+    2
+}
+
+fn default_mute_button() -> u8 {
+    3
+}
+
+/// What happens to services that don't get an LED of their own.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OverflowConfig {
+    #[serde(default)]
+    pub policy: OverflowPolicy,
+    /// The LED shared by overflowing services. It is never handed out to a single service.
+    pub led: Option<u16>,
+    /// Seconds each overflowing service is shown for with the `pager` policy.
+    pub page_interval: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    /// Overflowing services aren't shown.
+    #[default]
+    None,
+    /// Overflowing services take turns on the shared LED.
+    Pager,
+    /// The shared LED shows the worst status of all overflowing services.
+    Aggregate,
+}
+
+impl DeviceConfig {
+    /// Every LED on the device, reserved or not.
+    pub fn leds(&self) -> Range<u16> {
+        let first = self.first_led.unwrap_or(self.profile.first_led());
+        let count = self.led_count.or(self.profile.led_count()).unwrap_or(0);
+        first..first.saturating_add(count)
+    }
+}
+
+/// A monitored service. Exactly one of `unit`, `http`, `tcp`, `process`, `pid_file` and `command`
+/// says how its status is checked.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    /// A systemd unit, or a glob pattern such as `worker@*.service` matching any number of them.
+    pub unit: Option<String>,
+    /// Which systemd manager on this machine `unit` belongs to.
+    #[serde(default)]
+    pub scope: Scope,
+    /// The host in `hosts` whose `unit` this is, when it isn't on this machine.
+    pub host: Option<String>,
+    /// The agent in `server.agents` that reports this service, by its `unit` or `name` there.
+    pub agent: Option<String>,
+    pub http: Option<HttpCheck>,
+    /// A `host:port` that has to accept TCP connections.
+    pub tcp: Option<String>,
+    /// A process name, as in `/proc/<pid>/comm`, that has to be running.
+    pub process: Option<String>,
+    /// A PID file whose process has to be running.
+    pub pid_file: Option<PathBuf>,
+    /// A shell command that exits with 0 when the service is healthy.
+    pub command: Option<String>,
+    /// Statuses for other exit codes of `command`, e.g. `3 = "offline"`. Anything not listed is
+    /// `failed`.
+    #[serde(default)]
+    pub exit_codes: HashMap<String, Status>,
+    /// Seconds `http`, `tcp` and `command` checks may take.
+    pub timeout: Option<u64>,
+    /// Shown in logs instead of the unit name. Required for anything but systemd units.
+    pub name: Option<String>,
+    /// Pins the service to this LED instead of taking the next free one.
+    pub led: Option<u16>,
+    pub poll_interval: Option<u64>,
+    /// When there are more services than LEDs, services with a higher priority take LEDs from
+    /// those with a lower one.
+    #[serde(default)]
+    pub priority: i32,
+    /// Overrides the Particle function called for a status, e.g. `failed = "setOffline"`.
+    #[serde(default)]
+    pub functions: HashMap<Status, String>,
+    /// The devices showing the service, by name. Every device shows it when empty.
+    #[serde(default)]
+    pub devices: Vec<String>,
+}
+
+/// A systemd manager on this machine, like `systemctl`'s `--system`, `--user` and `--machine`.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// The system manager, `scope = "system"`.
+    #[default]
+    System,
+    /// The user manager of a UID, `scope = { user = 1000 }`.
+    User(u32),
+    /// The system manager of a local container, e.g. an nspawn one, `scope = { machine = "name" }`.
+    Machine(String),
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scope::System => write!(f, "system"),
+            Scope::User(uid) => write!(f, "user:{}", uid),
+            Scope::Machine(name) => write!(f, "machine:{}", name),
+        }
+    }
+}
+
+/// A machine reached over SSH, with key authentication only.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    /// The host name or IP address to connect to.
+    pub address: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// The private key to log in with, instead of ssh's defaults.
+    pub identity_file: Option<PathBuf>,
+    /// Seconds the host gets to answer before it counts as unreachable.
+    pub timeout: Option<u64>,
+}
+
+impl HostConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_HOST_TIMEOUT))
+    }
+}
+
+/// How an agent reports its services to the server.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AgentConfig {
+    /// The agent's name in the server's `server.agents`.
+    pub name: String,
+    /// The server's base URL, e.g. `http://central:8700`. The server itself only speaks plain
+    /// HTTP, so `https://` takes a TLS proxy in front of it.
+    pub server: String,
+    pub token: String,
+    /// Seconds between reports when nothing changes, so the server knows the agent is alive.
+    pub heartbeat: Option<u64>,
+}
+
+impl AgentConfig {
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat.unwrap_or(DEFAULT_HEARTBEAT))
+    }
+
+    pub fn server(&self) -> &str {
+        self.server.trim_end_matches('/')
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address to accept reports on, e.g. `0.0.0.0:8700`.
+    pub listen: String,
+    /// The agents allowed to report, by name.
+    #[serde(default)]
+    pub agents: HashMap<String, RemoteAgentConfig>,
+}
+
+/// An agent the server accepts reports from.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteAgentConfig {
+    /// The token the agent authenticates with.
+    pub token: String,
+    /// Seconds without a report before the agent's services are shown as lost.
+    pub timeout: Option<u64>,
+}
+
+impl RemoteAgentConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_AGENT_TIMEOUT))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HttpCheck {
+    pub url: String,
+    /// The expected status code. Any 2xx code is accepted when unset.
+    pub status: Option<u16>,
+    /// Text the response body has to contain.
+    pub body: Option<String>,
+}
+
+impl ServiceConfig {
+    /// Identifies the service: its unit for systemd units, otherwise its name. Services on other
+    /// machines are prefixed with their host or agent, as `host:unit`, and units outside the
+    /// system manager with their scope, as `user:1000:unit` or `machine:name:unit`.
+    pub fn id(&self) -> Cow<'_, str> {
+        match (self.host.as_ref().or(self.agent.as_ref()), &self.scope) {
+            (Some(machine), _) => Cow::Owned(format!("{}:{}", machine, self.local_id())),
+            (None, Scope::System) => self.local_id(),
+            (None, scope) => Cow::Owned(format!("{}:{}", scope, self.local_id())),
+        }
+    }
+
+    /// The unit, if it is a systemd unit on this machine.
+    pub fn local_unit(&self) -> Option<&str> {
+        match (&self.host, &self.agent) {
+            (None, None) => self.unit.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether `unit` is a pattern, which stands for every loaded unit it matches.
+    pub fn is_pattern(&self) -> bool {
+        self.unit
+            .as_deref()
+            .is_some_and(|unit| unit.contains(['*', '?', '[']))
+    }
+
+    /// What the ID of a unit matching this service's pattern starts with, before the unit's full
+    /// name, so it is scoped like the pattern.
+    pub fn id_prefix(&self) -> String {
+        match &self.scope {
+            Scope::System => String::new(),
+            scope => format!("{}:", scope),
+        }
+    }
+
+    /// Whether `id` is this service, or one of the units its pattern matches.
+    pub fn matches(&self, id: &str) -> bool {
+        if self.id() == id {
+            return true;
+        }
+        match (
+            self.is_pattern(),
+            &self.unit,
+            id.strip_prefix(&self.id_prefix()),
+        ) {
+            (true, Some(pattern), Some(unit)) => glob_match(pattern, unit),
+            _ => false,
+        }
+    }
+
+    /// Identifies the service on the machine it runs on. Units go by their full name, so `nginx`
+    /// is the same service as `nginx.service`, or a unit `nginx.service` matched by a pattern.
+    pub fn local_id(&self) -> Cow<'_, str> {
+        match (&self.unit, &self.name) {
+            (Some(unit), _) => Cow::Owned(full_unit_name(unit)),
+            (None, Some(name)) => Cow::Borrowed(name),
+            (None, None) => Cow::Borrowed(""),
+        }
+    }
+
+    pub fn display_name(&self) -> Cow<'_, str> {
+        match &self.name {
+            Some(name) => Cow::Borrowed(name),
+            None => self.id(),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_CHECK_TIMEOUT))
+    }
+
+    fn check_count(&self) -> usize {
+        [
+            self.unit.is_some(),
+            self.http.is_some(),
+            self.tcp.is_some(),
+            self.process.is_some(),
+            self.pid_file.is_some(),
+            self.command.is_some(),
+        ]
+        .into_iter()
+        .filter(|given| *given)
+        .count()
+    }
+}
+
+/// What the daemon runs as, picked by the subcommand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Checks services and drives the devices.
+    Standalone,
+    /// Checks services and reports them to a server, without any device.
+    Agent,
+    /// Drives the devices, also showing services that agents report.
+    Server,
+}
+
+impl Config {
+    pub fn load(path: &Path, mode: Mode) -> Result<Config, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| ConfigError::Io(path.to_path_buf(), error))?;
+        Config::parse(path, &contents, mode)
+    }
+
+    /// Parses and validates `contents`, as read from `path`.
+    fn parse(path: &Path, contents: &str, mode: Mode) -> Result<Config, ConfigError> {
+        let mut config: Config = toml::from_str(contents)
+            .map_err(|error| ConfigError::Parse(path.to_path_buf(), error))?;
+        if let Some(device) = config.device.take() {
+            if !config.devices.is_empty() {
+                return Err(ConfigError::Invalid(vec![
+                    "use either [device] or [[devices]], not both".to_string(),
+                ]));
+            }
+            config.devices.push(device);
+        }
+        config.validate(mode)?;
+        Ok(config)
+    }
+
+    /// Reports every problem at once rather than stopping at the first.
+    fn validate(&self, mode: Mode) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let mut units = HashSet::new();
+        let mut device_names = HashSet::new();
+
+        if self.devices.is_empty() && mode != Mode::Agent {
+            problems.push("a [device] or at least one [[devices]] is required".to_string());
+        }
+        match (&self.agent, mode) {
+            (None, Mode::Agent) => problems.push("agent mode needs an [agent] section".to_string()),
+            (Some(agent), _) => {
+                if agent.name.is_empty() {
+                    problems.push("agent.name must not be empty".to_string());
+                }
+                if !agent.server.starts_with("http://") && !agent.server.starts_with("https://") {
+                    problems.push(format!(
+                        "agent.server {:?} is not an HTTP(S) URL",
+                        agent.server
+                    ));
+                }
+                if agent.token.is_empty() {
+                    problems.push("agent.token must not be empty".to_string());
+                }
+                if agent.heartbeat == Some(0) {
+                    problems.push("agent.heartbeat must be at least 1 second".to_string());
+                }
+            }
+            (None, _) => {}
+        }
+        match (&self.server, mode) {
+            (None, Mode::Server) => {
+                problems.push("server mode needs a [server] section".to_string())
+            }
+            (Some(server), _) => {
+                for (name, agent) in &server.agents {
+                    if agent.token.is_empty() {
+                        problems.push(format!("server.agents.{}.token must not be empty", name));
+                    }
+                    if agent.timeout == Some(0) {
+                        problems.push(format!(
+                            "server.agents.{}.timeout must be at least 1 second",
+                            name
+                        ));
+                    }
+                }
+            }
+            (None, _) => {}
+        }
+        if self.poll_interval == Some(0) {
+            problems.push("poll_interval must be at least 1 second".to_string());
+        }
+        if self.resync_interval == Some(0) {
+            problems.push("resync_interval must be at least 1 second".to_string());
+        }
+        if self.particle.connect_timeout == Some(0) {
+            problems.push("particle.connect_timeout must be at least 1 second".to_string());
+        }
+        if self.particle.timeout == Some(0) {
+            problems.push("particle.timeout must be at least 1 second".to_string());
+        }
+        if let Some(url) = &self.particle.api_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                problems.push(format!("particle.api_url {:?} is not an HTTP(S) URL", url));
+            }
+        }
+        let buttons = [
+            ("acknowledge", self.buttons.acknowledge),
+            ("detail", self.buttons.detail),
+            ("mute", self.buttons.mute),
+        ];
+        for (i, (action, button)) in buttons.iter().enumerate() {
+            if !(1..=BUTTON_COUNT).contains(button) {
+                problems.push(format!(
+                    "buttons.{} must be a button from 1 to {}",
+                    action, BUTTON_COUNT
+                ));
+            }
+            if let Some((other, _)) = buttons[..i].iter().find(|(_, other)| other == button) {
+                problems.push(format!(
+                    "buttons.{} and buttons.{} are both button {}",
+                    other, action, button
+                ));
+            }
+        }
+        if self.buttons.mute_duration == Some(0) {
+            problems.push("buttons.mute_duration must be at least 1 second".to_string());
+        }
+
+        for (name, host) in &self.hosts {
+            if host.address.is_empty() {
+                problems.push(format!("hosts.{}.address must not be empty", name));
+            }
+            if host.timeout == Some(0) {
+                problems.push(format!("hosts.{}.timeout must be at least 1 second", name));
+            }
+        }
+
+        for device in &self.devices {
+            // Problems are reported as in a single [device] section when there is only one.
+            let (field, context) = if self.devices.len() == 1 {
+                ("device".to_string(), String::new())
+            } else {
+                (
+                    format!("devices.{}", device.name),
+                    format!("devices.{}: ", device.name),
+                )
+            };
+
+            if device.name.is_empty() {
+                problems.push("device.name must not be empty".to_string());
+            } else if !device_names.insert(&device.name) {
+                problems.push(format!("device {} is listed more than once", device.name));
+            }
+
+            let all_leds = device.leds();
+            if device.profile == DeviceProfile::Custom && device.led_count.is_none() {
+                problems.push(format!(
+                    "{}.led_count is required for the custom profile",
+                    field
+                ));
+            } else if all_leds.is_empty() {
+                problems.push(format!("{}.led_count must be at least 1", field));
+            }
+            for led in &device.reserved {
+                if !all_leds.contains(led) {
+                    problems.push(format!(
+                        "{}.reserved: led {} is outside of {}",
+                        field,
+                        led,
+                        describe_leds(&all_leds)
+                    ));
+                }
+            }
+
+            let indicator = self.indicator(device);
+            if let IndicatorConfig::Sysfs { leds, .. } = indicator {
+                for led in leds.keys() {
+                    match led.parse::<u16>() {
+                        Ok(led) if all_leds.contains(&led) => {}
+                        Ok(led) => problems.push(format!(
+                            "{}indicator.leds: led {} is outside of {}",
+                            context,
+                            led,
+                            describe_leds(&all_leds)
+                        )),
+                        Err(_) => problems.push(format!(
+                            "{}indicator.leds: {} is not a number",
+                            context, led
+                        )),
+                    }
+                }
+            }
+            if !self.styles.is_empty() && !matches!(indicator, IndicatorConfig::Particle) {
+                problems.push(format!(
+                    "{}styles are only supported by the particle indicator",
+                    context
+                ));
+            }
+
+            let overflow = self.overflow(device);
+            if overflow.policy != OverflowPolicy::None {
+                match overflow.led {
+                    None => {
+                        problems.push(format!("{}overflow.led is required by its policy", context))
+                    }
+                    Some(led) if !all_leds.contains(&led) => problems.push(format!(
+                        "{}overflow.led: led {} is outside of {}",
+                        context,
+                        led,
+                        describe_leds(&all_leds)
+                    )),
+                    Some(_) => {}
+                }
+            }
+            if overflow.page_interval == Some(0) {
+                problems.push(format!(
+                    "{}overflow.page_interval must be at least 1 second",
+                    context
+                ));
+            }
+
+            let mut leds = HashMap::new();
+            for service in self.services_on(device) {
+                let Some(led) = service.led else {
+                    continue;
+                };
+                if !all_leds.contains(&led) {
+                    problems.push(format!(
+                        "{}: {}led {} is outside of {}",
+                        service.id(),
+                        context,
+                        led,
+                        describe_leds(&all_leds)
+                    ));
+                } else if overflow.led == Some(led) && overflow.policy != OverflowPolicy::None {
+                    problems.push(format!(
+                        "{}: {}led {} is the shared overflow LED",
+                        service.id(),
+                        context,
+                        led
+                    ));
+                } else if let Some(other) = leds.insert(led, service.id()) {
+                    problems.push(format!(
+                        "{}: {}led {} is already pinned to {}",
+                        service.id(),
+                        context,
+                        led,
+                        other
+                    ));
+                }
+            }
+        }
+
+        for service in &self.services {
+            if service.id().is_empty() {
+                problems.push("services that aren't systemd units need a name".to_string());
+                continue;
+            }
+            if service.agent.is_some() {
+                // The agent does the checking, so its services are only named here.
+                if service.check_count() != usize::from(service.unit.is_some()) {
+                    problems.push(format!(
+                        "{}: services reported by an agent only take a unit or a name",
+                        service.id()
+                    ));
+                }
+            } else if service.check_count() != 1 {
+                problems.push(format!(
+                    "{}: exactly one of unit, http, tcp, process, pid_file and command must be given",
+                    service.id()
+                ));
+            }
+            if !units.insert(service.id()) {
+                problems.push(format!("{} is listed more than once", service.id()));
+            }
+            if service.timeout == Some(0) {
+                problems.push(format!(
+                    "{}: timeout must be at least 1 second",
+                    service.id()
+                ));
+            }
+            if !service.exit_codes.is_empty() && service.command.is_none() {
+                problems.push(format!(
+                    "{}: exit_codes only apply to command checks",
+                    service.id()
+                ));
+            }
+            for code in service.exit_codes.keys() {
+                if code.parse::<i32>().is_err() {
+                    problems.push(format!(
+                        "{}: exit code {} is not a number",
+                        service.id(),
+                        code
+                    ));
+                }
+            }
+            if service.poll_interval == Some(0) {
+                problems.push(format!(
+                    "{}: poll_interval must be at least 1 second",
+                    service.id()
+                ));
+            }
+            if let Some(agent) = &service.agent {
+                if mode != Mode::Server {
+                    problems.push(format!(
+                        "{}: services reported by an agent need the server subcommand",
+                        service.id()
+                    ));
+                } else if !self
+                    .server
+                    .as_ref()
+                    .is_some_and(|server| server.agents.contains_key(agent))
+                {
+                    problems.push(format!(
+                        "{}: there is no agent named {}",
+                        service.id(),
+                        agent
+                    ));
+                }
+                if service.host.is_some() {
+                    problems.push(format!(
+                        "{}: use either host or agent, not both",
+                        service.id()
+                    ));
+                }
+            }
+            if service.is_pattern() {
+                if service.host.is_some() || service.agent.is_some() {
+                    problems.push(format!(
+                        "{}: unit patterns only apply to units on this machine",
+                        service.id()
+                    ));
+                }
+                if service.led.is_some() {
+                    problems.push(format!(
+                        "{}: a unit pattern can't be pinned to an LED, as it may match several units",
+                        service.id()
+                    ));
+                }
+                if service.name.is_some() {
+                    problems.push(format!(
+                        "{}: the units a pattern matches are shown by their own names",
+                        service.id()
+                    ));
+                }
+            }
+            if service.scope != Scope::System {
+                if service.unit.is_none() {
+                    problems.push(format!(
+                        "{}: scope only applies to systemd units",
+                        service.id()
+                    ));
+                }
+                if service.host.is_some() || service.agent.is_some() {
+                    problems.push(format!(
+                        "{}: scope only applies to units on this machine",
+                        service.id()
+                    ));
+                }
+            }
+            if service.scope == Scope::Machine(String::new()) {
+                problems.push(format!("{}: scope.machine must not be empty", service.id()));
+            }
+            if let Some(host) = &service.host {
+                if service.unit.is_none() {
+                    problems.push(format!(
+                        "{}: host only applies to systemd units",
+                        service.id()
+                    ));
+                }
+                if !self.hosts.contains_key(host) {
+                    problems.push(format!("{}: there is no host named {}", service.id(), host));
+                }
+            }
+            for device in &service.devices {
+                if !self.devices.iter().any(|other| &other.name == device) {
+                    problems.push(format!(
+                        "{}: there is no device named {}",
+                        service.id(),
+                        device
+                    ));
+                }
+            }
+            for (status, function) in &service.functions {
+                if function.is_empty() {
+                    problems.push(format!(
+                        "{}: the function for {:?} must not be empty",
+                        service.id(),
+                        status
+                    ));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    pub fn resync_interval(&self) -> Duration {
+        Duration::from_secs(self.resync_interval.unwrap_or(DEFAULT_RESYNC_INTERVAL))
+    }
+
+    /// Where `device`'s LED assignments are remembered. With several devices, each gets its own
+    /// file next to the top-level `state_file`, unless it sets one.
+    pub fn state_file(&self, device: &DeviceConfig) -> PathBuf {
+        if let Some(state_file) = &device.state_file {
+            return state_file.clone();
+        }
+        let state_file = self
+            .state_file
+            .clone()
+            .unwrap_or_else(|| DEFAULT_STATE_FILE.into());
+        if self.devices.len() == 1 {
+            return state_file;
+        }
+
+        // led_state.toml becomes led_state.<device>.toml.
+        let stem = state_file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = match state_file.extension() {
+            Some(extension) => format!("{}.{}.{}", stem, device.name, extension.to_string_lossy()),
+            None => format!("{}.{}", stem, device.name),
+        };
+        state_file.with_file_name(name)
+    }
+
+    pub fn indicator<'a>(&'a self, device: &'a DeviceConfig) -> &'a IndicatorConfig {
+        device.indicator.as_ref().unwrap_or(&self.indicator)
+    }
+
+    pub fn overflow<'a>(&'a self, device: &'a DeviceConfig) -> &'a OverflowConfig {
+        device.overflow.as_ref().unwrap_or(&self.overflow)
+    }
+
+    /// The services shown on `device`.
+    pub fn services_on<'a>(
+        &'a self,
+        device: &'a DeviceConfig,
+    ) -> impl Iterator<Item = &'a ServiceConfig> + 'a {
+        self.services
+            .iter()
+            .filter(|service| service.devices.is_empty() || service.devices.contains(&device.name))
+    }
+
+    /// The service `id` belongs to: the one with that ID, otherwise the first pattern matching it.
+    pub fn service(&self, id: &str) -> Option<&ServiceConfig> {
+        self.services
+            .iter()
+            .find(|service| service.id() == id)
+            .or_else(|| self.services.iter().find(|service| service.matches(id)))
+    }
+
+    /// The LEDs on `device` that can be handed out to services automatically.
+    pub fn assignable_leds<'a>(
+        &'a self,
+        device: &'a DeviceConfig,
+    ) -> impl Iterator<Item = u16> + 'a {
+        let overflow = self.overflow(device);
+        let overflow_led = match overflow.policy {
+            OverflowPolicy::None => None,
+            _ => overflow.led,
+        };
+        device
+            .leds()
+            .filter(move |led| !device.reserved.contains(led) && Some(*led) != overflow_led)
+    }
+
+    /// The units that are pinned to an LED on `device`.
+    pub fn pinned_leds(&self, device: &DeviceConfig) -> HashMap<String, u16> {
+        self.services_on(device)
+            .filter_map(|service| service.led.map(|led| (service.id().to_string(), led)))
+            .collect()
+    }
+
+    /// The poll interval for `service`, or `default` if the config doesn't set one.
+    pub fn poll_interval(&self, service: &ServiceConfig, default: Duration) -> Duration {
+        service
+            .poll_interval
+            .or(self.poll_interval)
+            .map(Duration::from_secs)
+            .unwrap_or(default)
+    }
+}
+
+fn describe_leds(leds: &Range<u16>) -> String {
+    if leds.is_empty() {
+        "the device's (empty) LED range".to_string()
+    } else {
+        format!("{}..={}", leds.start, leds.end - 1)
+    }
+}
+
+/// The mode picked by the `agent` or `server` subcommand, if any, and the config file named after
+/// it, in `APP_STATUS_CONFIG`, or `config.toml`.
+pub fn args() -> (Mode, PathBuf) {
+    let mut args = std::env::args().skip(1).peekable();
+    let mode = match args.peek().map(String::as_str) {
+        Some("agent") => Mode::Agent,
+        Some("server") => Mode::Server,
+        _ => Mode::Standalone,
+    };
+    if mode != Mode::Standalone {
+        args.next();
+    }
+    let path = args
+        .next()
+        .or_else(|| std::env::var("APP_STATUS_CONFIG").ok())
+        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string())
+        .into();
+    (mode, path)
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, error) => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            ConfigError::Parse(path, error) => {
+                write!(f, "could not parse {}: {}", path.display(), error)
+            }
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid config:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(toml: &str) -> ServiceConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn parse(toml: &str) -> Result<Config, ConfigError> {
+        Config::parse(Path::new("config.toml"), toml, Mode::Standalone)
+    }
+
+    /// The problems validating `toml` finds, which has to be invalid.
+    fn problems(toml: &str) -> Vec<String> {
+        match parse(toml) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            Err(error) => panic!("expected validation problems, got {}", error),
+            Ok(_) => panic!("expected validation problems, but the config is valid"),
+        }
+    }
+
+    const DEVICE: &str = "[device]\nname = \"rack\"\n";
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let error = parse(
+            r#"
+poll_interval = 0
+
+[device]
+name = "rack"
+
+[[services]]
+name = "api"
+
+[[services]]
+unit = "nginx"
+led = 12
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid config:\n  \
+             - poll_interval must be at least 1 second\n  \
+             - nginx.service: led 12 is outside of 1..=11\n  \
+             - api: exactly one of unit, http, tcp, process, pid_file and command must be given"
+        );
+    }
+
+    #[test]
+    fn a_valid_config_loads() {
+        let config = parse(&format!(
+            "{}\n[[services]]\nunit = \"nginx\"\nled = 3\n",
+            DEVICE
+        ))
+        .unwrap();
+        assert_eq!(config.devices.len(), 1);
+        assert_eq!(config.services[0].led, Some(3));
+    }
+
+    #[test]
+    fn one_device_section_or_the_other() {
+        let problems = problems(
+            r#"
+[device]
+name = "rack1"
+
+[[devices]]
+name = "rack2"
+"#,
+        );
+        assert_eq!(problems, ["use either [device] or [[devices]], not both"]);
+    }
+
+    #[test]
+    fn an_led_is_pinned_once() {
+        let problems = problems(&format!(
+            "{}\n[[services]]\nunit = \"a\"\nled = 2\n\n[[services]]\nunit = \"b\"\nled = 2\n",
+            DEVICE
+        ));
+        assert_eq!(
+            problems,
+            ["b.service: led 2 is already pinned to a.service"]
+        );
+    }
+
+    #[test]
+    fn pinned_leds_are_on_the_device() {
+        let problems = problems(
+            r#"
+[device]
+name = "rack"
+profile = "neopixel-24"
+overflow = { policy = "aggregate", led = 23 }
+
+[[services]]
+unit = "a"
+led = 24
+
+[[services]]
+unit = "b"
+led = 23
+"#,
+        );
+        assert_eq!(
+            problems,
+            [
+                "a.service: led 24 is outside of 0..=23",
+                "b.service: led 23 is the shared overflow LED",
+            ]
+        );
+    }
+
+    #[test]
+    fn reserved_leds_can_still_be_pinned() {
+        let config = parse(
+            r#"
+[device]
+name = "rack"
+reserved = [1]
+
+[[services]]
+unit = "a"
+led = 1
+"#,
+        );
+        assert!(config.is_ok());
+    }
+
+    /// The LEDs handed out automatically on the only device of `toml`.
+    fn assignable(toml: &str) -> Vec<u16> {
+        let config = parse(toml).unwrap();
+        config.assignable_leds(&config.devices[0]).collect()
+    }
+
+    #[test]
+    fn profiles_set_the_led_range() {
+        assert_eq!(assignable(DEVICE), (1..=11).collect::<Vec<_>>());
+        assert_eq!(
+            assignable(&format!("{}profile = \"neopixel-24\"\n", DEVICE)),
+            (0..=23).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            assignable(&format!("{}profile = \"neopixel-60\"\n", DEVICE)),
+            (0..=59).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn first_led_and_led_count_override_the_profile() {
+        assert_eq!(
+            assignable(&format!("{}first_led = 0\n", DEVICE)),
+            (0..=10).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            assignable(&format!("{}led_count = 4\n", DEVICE)),
+            [1, 2, 3, 4]
+        );
+        assert_eq!(
+            assignable(&format!(
+                "{}profile = \"custom\"\nfirst_led = 5\nled_count = 3\n",
+                DEVICE
+            )),
+            [5, 6, 7]
+        );
+    }
+
+    #[test]
+    fn reserved_and_overflow_leds_are_not_assignable() {
+        assert_eq!(
+            assignable(&format!(
+                "{}led_count = 6\nreserved = [1, 4]\noverflow = {{ policy = \"pager\", led = 6 }}\n",
+                DEVICE
+            )),
+            [2, 3, 5]
+        );
+        // Without a policy, the overflow LED is an ordinary one.
+        assert_eq!(
+            assignable(&format!(
+                "{}led_count = 3\noverflow = {{ led = 3 }}\n",
+                DEVICE
+            )),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn led_ranges_are_checked() {
+        assert_eq!(
+            problems(&format!("{}profile = \"custom\"\n", DEVICE)),
+            ["device.led_count is required for the custom profile"]
+        );
+        assert_eq!(
+            problems(&format!("{}led_count = 0\n", DEVICE)),
+            ["device.led_count must be at least 1"]
+        );
+        assert_eq!(
+            problems(&format!("{}reserved = [0, 12]\n", DEVICE)),
+            [
+                "device.reserved: led 0 is outside of 1..=11",
+                "device.reserved: led 12 is outside of 1..=11",
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_have_no_led_or_name() {
+        let problems = problems(&format!(
+            "{}\n[[services]]\nunit = \"worker@*\"\nled = 1\nname = \"workers\"\n",
+            DEVICE
+        ));
+        assert_eq!(
+            problems,
+            [
+                "worker@*.service: a unit pattern can't be pinned to an LED, as it may match several units",
+                "worker@*.service: the units a pattern matches are shown by their own names",
+            ]
+        );
+    }
+
+    #[test]
+    fn units_go_by_their_full_name() {
+        assert_eq!(service(r#"unit = "nginx""#).id(), "nginx.service");
+        assert_eq!(service(r#"unit = "nginx.socket""#).id(), "nginx.socket");
+        assert_eq!(service(r#"name = "api""#).id(), "api");
+    }
+
+    #[test]
+    fn units_matched_by_a_pattern_have_the_same_id_as_on_their_own() {
+        for scope in ["", "scope = { user = 1000 }\n"] {
+            let single = service(&format!("{}unit = \"worker@1\"", scope));
+            let pattern = service(&format!("{}unit = \"worker@*\"", scope));
+            let matched = format!("{}{}", pattern.id_prefix(), "worker@1.service");
+            assert_eq!(single.id(), matched);
+            assert!(pattern.matches(&matched));
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use crate::buttons::Buttons;
+use crate::config::{AlertConfig, Config, DeviceConfig};
+use crate::indicators::{self, Indicator};
+use crate::leds::LedAllocator;
+use crate::overflow::Overflow;
+use crate::{App, Event, Status};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+/// One device showing services on its LEDs, with its own LED pool and its own idea of what it
+/// currently shows.
+pub struct Device {
+    pub name: String,
+    indicator: Box<dyn Indicator>,
+    leds: LedAllocator,
+    apps: HashMap<String, App>,
+    /// LEDs of services that went away, shown as unknown until that goes through or another
+    /// service takes the LED, so they don't keep showing the last status, or keep blinking.
+    released: Vec<App>,
+    overflow: Option<Overflow>,
+    buttons: Buttons,
+    /// The configured IDs of the services shown on this device, which may be unit patterns.
+    services: Vec<String>,
+    online: bool,
+    resync_requested: bool,
+    next_resync: Instant,
+}
+
+impl Device {
+    pub fn new(config: &Config, device: &DeviceConfig) -> Result<Device, Box<dyn Error>> {
+        let indicator = indicators::from_config(config, device)
+            .map_err(|error| format!("{}: {}", device.name, error))?;
+
+        Ok(Device {
+            name: device.name.clone(),
+            indicator,
+            leds: LedAllocator::new(
+                config.assignable_leds(device),
+                config.pinned_leds(device),
+                config.state_file(device),
+            ),
+            apps: HashMap::new(),
+            released: Vec::new(),
+            overflow: Overflow::new(config.overflow(device)),
+            buttons: Buttons::new(&config.buttons),
+            services: config
+                .services_on(device)
+                .map(|service| service.id().to_string())
+                .collect(),
+            online: true,
+            resync_requested: false,
+            next_resync: Instant::now() + config.resync_interval(),
+        })
+    }
+
+    pub fn watch(&mut self, sender: Sender<Event>) {
+        self.indicator.watch(sender);
+    }
+
+    /// Brings the LEDs up to date with `statuses`, the latest status of every service that could
+    /// be read.
+    pub fn update(&mut self, config: &Config, statuses: &[(String, Status)]) {
+        let statuses: Vec<&(String, Status)> = statuses
+            .iter()
+            .filter(|(id, _)| {
+                config
+                    .service(id)
+                    .is_some_and(|service| self.services.iter().any(|own| *own == service.id()))
+            })
+            .collect();
+
+        // Frees up an LED if a process status is no longer present.
+        let app_names: Vec<String> = self.apps.keys().cloned().collect();
+        for app_name in app_names {
+            if !statuses.iter().any(|(name, _)| name == &app_name) {
+                if let Some(App { name, led_num, .. }) = self.apps.remove(&app_name) {
+                    self.leds.release(&app_name, led_num);
+                    if led_num.is_some() {
+                        let name = format!("{} (gone)", name);
+                        self.released.push(App::new(name, Status::Unknown, led_num));
+                    }
+                }
+            }
+        }
+
+        for (app_name, _) in &statuses {
+            if let Some(service) = config.service(app_name) {
+                let leds = &mut self.leds;
+                self.apps
+                    .entry(app_name.clone())
+                    .or_insert_with_key(|unit| {
+                        if service.is_pattern() {
+                            leds.forget(unit);
+                        }
+                        App::from_service(service, unit, Status::Unknown, leds.assign(unit))
+                    });
+            }
+        }
+        rebalance_leds(&mut self.apps, &mut self.leds);
+        let taken: HashSet<u16> = self.all_apps().filter_map(|app| app.led_num).collect();
+        self.released
+            .retain(|app| app.led_num.is_some_and(|led| !taken.contains(&led)));
+
+        // Iterate through the list of processes and turn on LEDs to reflect their state.
+        for (app_name, status) in statuses {
+            if let Some(app) = self.apps.get_mut(app_name) {
+                if app.last_status != *status {
+                    update_app(app, *status, &config.alerts)
+                }
+            }
+        }
+
+        if let Some(overflow) = self.overflow.as_mut() {
+            let mut overflowed: Vec<&App> = self
+                .apps
+                .values()
+                .filter(|app| app.led_num.is_none())
+                .collect();
+            let status = overflow.status(&mut overflowed);
+            if overflow.app.last_status != status {
+                update_app(&mut overflow.app, status, &config.alerts);
+                // The pager changes the status all the time, which isn't worth flashing about.
+                overflow.app.flash = false;
+            }
+        }
+
+        // The device can lose its LEDs without us knowing, e.g. when it reboots, so every LED is
+        // sent again now and then, and whenever the device comes back.
+        let resync_due = Instant::now() >= self.next_resync;
+        if resync_due || self.resync_requested || self.indicator.reconnected() {
+            log!("Resyncing all LEDs on {}", self.name);
+            for app in self.all_apps() {
+                app.confirmed_status = None;
+                app.backoff.reset();
+            }
+            self.next_resync = Instant::now() + config.resync_interval();
+            self.resync_requested = false;
+        }
+
+        let apps = self
+            .apps
+            .values_mut()
+            .chain(self.overflow.as_mut().map(|overflow| &mut overflow.app));
+        self.buttons.apply_mute(apps);
+
+        // Updates wait while the device is known to be offline, and are all resent once it's back.
+        if self.online {
+            let apps = self
+                .apps
+                .values_mut()
+                .chain(self.overflow.as_mut().map(|overflow| &mut overflow.app))
+                .chain(self.released.iter_mut());
+            sync_apps(self.indicator.as_mut(), &self.name, apps);
+            self.released.retain(App::needs_sync);
+        }
+    }
+
+    /// How long until something on the device needs updating, without any new statuses.
+    pub fn until_next_update(&self) -> Duration {
+        let mut timeout = self.next_resync.saturating_duration_since(Instant::now());
+
+        // Retries LEDs whose last update didn't go through once their backoff runs out.
+        let apps = self
+            .apps
+            .values()
+            .chain(self.overflow.as_ref().map(|overflow| &overflow.app))
+            .chain(&self.released);
+        for app in apps.filter(|app| self.online && app.needs_sync()) {
+            if let Some(until_retry) = app.backoff.until_due() {
+                timeout = timeout.min(until_retry);
+            }
+        }
+        if let Some(until_next_page) = self.overflow.as_ref().and_then(Overflow::until_next_page) {
+            timeout = timeout.min(until_next_page);
+        }
+        if let Some(until_unmuted) = self.buttons.until_unmuted() {
+            timeout = timeout.min(until_unmuted);
+        }
+        timeout
+    }
+
+    /// Acts on an event from the device itself.
+    pub fn handle(&mut self, event: &Event) {
+        match event {
+            Event::DeviceOnline(_) => {
+                log!("{} is online, resuming LED updates", self.name);
+                self.online = true;
+                self.resync_requested = true;
+            }
+            Event::DeviceOffline(_) => {
+                if self.online {
+                    log!("{} went offline, pausing LED updates", self.name);
+                }
+                self.online = false;
+            }
+            Event::ButtonPressed(_, button) => {
+                let apps = self
+                    .apps
+                    .values_mut()
+                    .chain(self.overflow.as_mut().map(|overflow| &mut overflow.app));
+                self.buttons.press(*button, apps.collect());
+            }
+            Event::ServiceChanged(_) => {}
+        }
+    }
+
+    fn all_apps(&mut self) -> impl Iterator<Item = &mut App> {
+        self.apps
+            .values_mut()
+            .chain(self.overflow.as_mut().map(|overflow| &mut overflow.app))
+    }
+}
+
+/// Gives LEDs that have freed up to services without one, and lets services take LEDs from
+/// lower priority ones when there aren't enough to go around.
+fn rebalance_leds(apps: &mut HashMap<String, App>, leds: &mut LedAllocator) {
+    let mut waiting: Vec<(String, i32)> = apps
+        .iter()
+        .filter(|(_, app)| app.led_num.is_none())
+        .map(|(unit, app)| (unit.clone(), app.priority))
+        .collect();
+    waiting.sort_by_key(|(unit, priority)| (Reverse(*priority), unit.clone()));
+
+    for (unit, priority) in waiting {
+        let led = leds.assign(&unit).or_else(|| {
+            let (victim, _) = apps
+                .iter()
+                .filter(|(other, app)| {
+                    app.led_num.is_some() && app.priority < priority && !leds.is_pinned(other)
+                })
+                .min_by_key(|(_, app)| app.priority)?;
+            let victim = apps.get_mut(&victim.clone())?;
+            let led = victim.led_num.take()?;
+            victim.confirmed_status = None;
+            log!("{} gives up LED {} to {}", victim.name, led, unit);
+            leds.hand_over(&unit, led);
+            Some(led)
+        });
+
+        if let (Some(led), Some(app)) = (led, apps.get_mut(&unit)) {
+            app.led_num = Some(led);
+            // Makes sure the LED is updated to show its new owner.
+            app.confirmed_status = None;
+            app.backoff.reset();
+        }
+    }
+}
+
+fn update_app(app: &mut App, new_status: Status, alerts: &AlertConfig) {
+    // Services seen for the first time come in as unknown, which isn't worth flashing about.
+    app.flash = alerts.flash && !app.muted && app.last_status != Status::Unknown;
+    app.alerting = alerts.statuses.contains(&new_status);
+    app.last_status = new_status;
+    // A new status is worth trying right away, even while backing off from the last one.
+    app.backoff.reset();
+}
+
+/// Shows the status of every app on its LED, unless the device already acknowledged it or a retry
+/// isn't due yet. Everything is sent together, so indicators that can batch updates do.
+fn sync_apps<'a>(
+    indicator: &mut dyn Indicator,
+    device: &str,
+    apps: impl Iterator<Item = &'a mut App>,
+) {
+    let mut due: Vec<&mut App> = apps
+        .filter(|app| app.needs_sync() && app.backoff.is_due())
+        .collect();
+    if due.is_empty() {
+        return;
+    }
+
+    let leds: Vec<(u16, &App)> = due
+        .iter()
+        .filter_map(|app| Some((app.led_num?, &**app)))
+        .collect();
+    let results = indicator.show_all(&leds);
+
+    for (app, result) in due.iter_mut().zip(results) {
+        let led = app.led_num.unwrap_or_default();
+        match result {
+            Ok(()) => {
+                app.confirmed_status = Some(app.last_status);
+                app.flash = false;
+                app.backoff.reset();
+                log!(
+                    "Successfully updated LED {} on {} for {}",
+                    led,
+                    device,
+                    app.name
+                );
+            }
+            Err(error) => {
+                let delay = app.backoff.fail();
+                log!(
+                    "Error when updating LED {} on {} for {}, retrying in {:.1}s: {}",
+                    led,
+                    device,
+                    app.name,
+                    delay.as_secs_f32(),
+                    error
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(name: &str, priority: i32, led_num: Option<u16>) -> (String, App) {
+        let app = App {
+            priority,
+            ..App::new(name.to_string(), Status::Online, led_num)
+        };
+        (name.to_string(), app)
+    }
+
+    fn allocator(name: &str, leds: &[u16]) -> LedAllocator {
+        let path = std::env::temp_dir().join(format!(
+            "app_status_device_{}_{}.toml",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        LedAllocator::new(leds.iter().copied(), HashMap::new(), path)
+    }
+
+    #[test]
+    fn higher_priority_services_take_leds_from_lower_ones() {
+        let mut leds = allocator("steal", &[1, 2]);
+        leds.assign("low");
+        leds.assign("mid");
+        let mut apps: HashMap<String, App> = [
+            app("low", 0, Some(1)),
+            app("mid", 5, Some(2)),
+            app("high", 10, None),
+        ]
+        .into();
+
+        rebalance_leds(&mut apps, &mut leds);
+        assert_eq!(apps["high"].led_num, Some(1));
+        assert_eq!(apps["high"].confirmed_status, None);
+        assert_eq!(apps["low"].led_num, None);
+        assert_eq!(apps["mid"].led_num, Some(2));
+    }
+
+    #[test]
+    fn equal_priorities_keep_their_leds() {
+        let mut leds = allocator("equal", &[1]);
+        leds.assign("first");
+        let mut apps: HashMap<String, App> =
+            [app("first", 0, Some(1)), app("second", 0, None)].into();
+
+        rebalance_leds(&mut apps, &mut leds);
+        assert_eq!(apps["first"].led_num, Some(1));
+        assert_eq!(apps["second"].led_num, None);
+    }
+
+    #[test]
+    fn freed_leds_go_to_waiting_services_by_priority() {
+        let mut leds = allocator("freed", &[1, 2]);
+        leds.assign("gone");
+        leds.assign("stays");
+        leds.release("gone", Some(1));
+        let mut apps: HashMap<String, App> = [
+            app("stays", 0, Some(2)),
+            app("low", 0, None),
+            app("high", 3, None),
+        ]
+        .into();
+
+        rebalance_leds(&mut apps, &mut leds);
+        assert_eq!(apps["high"].led_num, Some(1));
+        assert_eq!(apps["low"].led_num, None);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::config::{Config, DeviceConfig, IndicatorConfig};
+use crate::{App, Event};
+use std::env;
+use std::error::Error;
+use std::sync::mpsc::Sender;
+
+mod particle;
+mod particle_events;
+mod serial;
+mod sysfs;
+mod terminal;
+mod webhook;
+
+/// Something that can show a service's status on one of its LEDs.
+pub trait Indicator {
+    /// Shows `app`'s `last_status` on `led`.
+    fn show(&mut self, led: u16, app: &App) -> Result<(), Box<dyn Error>>;
+
+    /// Shows several LEDs at once, returning a result for each. Indicators that can update
+    /// several LEDs in one go override this.
+    fn show_all(&mut self, leds: &[(u16, &App)]) -> Vec<Result<(), Box<dyn Error>>> {
+        leds.iter().map(|(led, app)| self.show(*led, app)).collect()
+    }
+
+    /// Whether the device came back since this was last asked, in which case it may have lost
+    /// its LEDs.
+    fn reconnected(&mut self) -> bool {
+        false
+    }
+
+    /// Starts reporting the device going online and offline, and its buttons being pressed, to
+    /// `sender`, for indicators that can tell.
+    fn watch(&mut self, _sender: Sender<Event>) {}
+}
+
+/// Builds the indicator selected by `device`'s `indicator` section, or the top-level one.
+pub fn from_config(
+    config: &Config,
+    device: &DeviceConfig,
+) -> Result<Box<dyn Indicator>, Box<dyn Error>> {
+    Ok(match config.indicator(device) {
+        IndicatorConfig::Particle => {
+            let token = device
+                .access_token
+                .clone()
+                .or_else(|| env::var("ACCESS_TOKEN").ok())
+                .ok_or("Please provide a Particle access token!")?;
+            let mut cloud = particle::ParticleCloud::new(
+                &config.particle,
+                token,
+                device.name.clone(),
+                config.styles.clone(),
+            )?;
+            // Running against incompatible firmware is refused outright, but an unreachable
+            // device is checked again before the first update.
+            match cloud.check_version() {
+                Err(error @ particle::ParticleError::Incompatible(_)) => return Err(error.into()),
+                Err(error) => {
+                    log!("Could not check the firmware's protocol version: {}", error)
+                }
+                Ok(()) => {}
+            }
+            Box::new(cloud)
+        }
+        IndicatorConfig::Serial { path, baud } => Box::new(serial::Serial::open(path, *baud)?),
+        IndicatorConfig::Terminal => Box::new(terminal::Terminal::new()),
+        IndicatorConfig::Sysfs { leds, blink } => Box::new(sysfs::Sysfs::new(leds, blink)),
+        IndicatorConfig::Webhook { url } => {
+            Box::new(webhook::Webhook::new(url.clone(), device.name.clone())?)
+        }
+    })
+}
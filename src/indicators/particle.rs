@@ -0,0 +1,456 @@
+use super::{particle_events, Indicator};
+use crate::config::{ParticleConfig, StyleConfig};
+use crate::{App, Event, Status};
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// How long an idle connection to the API is kept open for the next call.
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+/// The version of firmware/PROTOCOL.md spoken here, which the firmware has to match.
+const PROTOCOL_VERSION: i32 = 6;
+/// The Particle variable holding the protocol version the firmware speaks.
+const VERSION_VARIABLE: &str = "protocolVersion";
+/// Sets an LED to a color and pattern, taking `<led>:<rrggbb>:<pattern>`.
+const STYLE_FUNCTION: &str = "setLed";
+/// Sets several LEDs in one call, taking `<led>:<status>` pairs separated by commas.
+const BATCH_FUNCTION: &str = "setLeds";
+/// The longest argument the Particle Cloud passes on to a function.
+const MAX_ARG_LEN: usize = 622;
+
+/// Calls a function on a Particle device through the Particle Cloud API.
+pub struct ParticleCloud {
+    api_url: String,
+    token: String,
+    device: String,
+    /// Statuses shown in a configured color and pattern rather than the firmware's own.
+    styles: HashMap<Status, StyleConfig>,
+    /// Shared by every call, so the TLS connection to the API is reused between LED changes.
+    client: Client,
+    /// Follows the event stream, which stays open indefinitely and so can't share the client's
+    /// overall timeout.
+    stream_client: Client,
+    /// Set when a call found the device disconnected, until a call goes through again.
+    disconnected: bool,
+    reconnected: bool,
+    /// Cleared when the firmware turns out not to have `setLeds`, after which LEDs are set one at
+    /// a time.
+    batched: bool,
+    /// Whether the firmware's protocol version has been read and found compatible.
+    version_checked: bool,
+    /// Set when the firmware predates the handshake, so it only has `setOnline`, `setOffline` and
+    /// `setUndefined`, and takes a bare LED number.
+    legacy: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ParticleFnResult {
+    id: String,
+    name: String,
+    connected: bool,
+    return_value: isize,
+}
+
+#[derive(Deserialize, Debug)]
+struct ParticleVariableResult {
+    result: i32,
+}
+
+/// The body of a failed API call, e.g. when the device is offline or the function doesn't exist.
+#[derive(Deserialize, Debug)]
+struct ParticleApiError {
+    error: String,
+}
+
+#[derive(Debug)]
+pub enum ParticleError {
+    Http(reqwest::Error),
+    /// The API refused the call, with its status code and error message.
+    Api(u16, String),
+    /// The cloud answered, but the device isn't connected to it.
+    Disconnected,
+    /// A device other than the configured one answered.
+    WrongDevice {
+        id: String,
+        name: String,
+    },
+    /// The firmware speaks another version of the protocol.
+    Incompatible(i32),
+    /// The function ran, but its return value says it failed.
+    Rejected {
+        function: String,
+        return_value: isize,
+    },
+}
+
+impl fmt::Display for ParticleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParticleError::Http(error) => write!(f, "{}", error),
+            ParticleError::Api(status, message) => {
+                write!(f, "the Particle API answered {}: {}", status, message)
+            }
+            ParticleError::Disconnected => write!(f, "the device is not connected"),
+            ParticleError::Incompatible(version) => write!(
+                f,
+                "the firmware speaks protocol version {}, but version {} is needed",
+                version, PROTOCOL_VERSION
+            ),
+            ParticleError::WrongDevice { id, name } => {
+                write!(
+                    f,
+                    "the call was answered by another device ({} / {})",
+                    name, id
+                )
+            }
+            ParticleError::Rejected {
+                function,
+                return_value,
+            } => write!(f, "{} returned {}", function, return_value),
+        }
+    }
+}
+
+impl Error for ParticleError {}
+
+impl From<reqwest::Error> for ParticleError {
+    fn from(error: reqwest::Error) -> ParticleError {
+        ParticleError::Http(error)
+    }
+}
+
+impl ParticleCloud {
+    pub fn new(
+        config: &ParticleConfig,
+        token: String,
+        device: String,
+        styles: HashMap<Status, StyleConfig>,
+    ) -> reqwest::Result<ParticleCloud> {
+        let client = Client::builder()
+            .user_agent(crate::USER_AGENT)
+            .connect_timeout(config.connect_timeout())
+            .timeout(config.timeout())
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_keepalive(TCP_KEEPALIVE)
+            .build()?;
+        let stream_client = Client::builder()
+            .user_agent(crate::USER_AGENT)
+            .connect_timeout(config.connect_timeout())
+            .tcp_keepalive(TCP_KEEPALIVE)
+            .build()?;
+
+        Ok(ParticleCloud {
+            api_url: config.api_url().to_string(),
+            token,
+            device,
+            styles,
+            client,
+            stream_client,
+            disconnected: false,
+            reconnected: false,
+            batched: true,
+            version_checked: false,
+            legacy: false,
+        })
+    }
+}
+
+impl Indicator for ParticleCloud {
+    fn show(&mut self, led: u16, app: &App) -> Result<(), Box<dyn Error>> {
+        self.check_version()?;
+        let (to_call, arg) = match (app.functions.get(&app.last_status), self.style(led, app)) {
+            (Some(function), _) if self.legacy => (function.clone(), led.to_string()),
+            (None, _) if self.legacy => (get_legacy_status_fn(&app.last_status), led.to_string()),
+            (Some(function), _) => (function.clone(), format!("{}{}", led, flags(app))),
+            (None, Some(style)) => (STYLE_FUNCTION.to_string(), style),
+            (None, None) => (
+                get_status_fn(&app.last_status),
+                format!("{}{}", led, flags(app)),
+            ),
+        };
+
+        // The firmware answers with the LED it changed, or a negative number on a bad argument.
+        let return_value = self.call_function(&to_call, &arg)?;
+        if return_value != led as isize {
+            return Err(ParticleError::Rejected {
+                function: to_call,
+                return_value,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn show_all(&mut self, leds: &[(u16, &App)]) -> Vec<Result<(), Box<dyn Error>>> {
+        if let Err(error) = self.check_version() {
+            let error = error.to_string();
+            return leds.iter().map(|_| Err(error.clone().into())).collect();
+        }
+        // A single LED goes through its own function, which any firmware has.
+        if leds.len() < 2 || self.legacy {
+            return leds.iter().map(|(led, app)| self.show(*led, app)).collect();
+        }
+
+        // LEDs with a custom function are left out of the batches.
+        let entries: Vec<Option<String>> = leds
+            .iter()
+            .map(|(led, app)| {
+                if app.functions.contains_key(&app.last_status) {
+                    return None;
+                }
+                Some(self.style(*led, app).unwrap_or_else(|| {
+                    format!("{}:{}{}", led, app.last_status.as_str(), flags(app))
+                }))
+            })
+            .collect();
+
+        let mut results: Vec<Option<Result<(), Box<dyn Error>>>> =
+            leds.iter().map(|_| None).collect();
+        for batch in batches(&entries) {
+            if self.batched {
+                let arg = batch
+                    .iter()
+                    .filter_map(|i| entries[*i].as_deref())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                // The firmware answers with the number of LEDs it set.
+                match self.call_function(BATCH_FUNCTION, &arg) {
+                    Ok(return_value) if return_value == batch.len() as isize => {
+                        for i in &batch {
+                            results[*i] = Some(Ok(()));
+                        }
+                        continue;
+                    }
+                    // The API also answers 404 for a device that is offline or misnamed, which
+                    // says nothing about its firmware.
+                    Err(ParticleError::Api(404, message)) if is_missing_function(&message) => {
+                        log!(
+                            "The device has no {} function, setting LEDs one at a time",
+                            BATCH_FUNCTION
+                        );
+                        self.batched = false;
+                    }
+                    result => {
+                        let error = match result {
+                            Ok(return_value) => ParticleError::Rejected {
+                                function: BATCH_FUNCTION.to_string(),
+                                return_value,
+                            },
+                            Err(error) => error,
+                        }
+                        .to_string();
+                        for i in &batch {
+                            results[*i] = Some(Err(error.clone().into()));
+                        }
+                        continue;
+                    }
+                }
+            }
+            for i in batch {
+                results[i] = Some(self.show(leds[i].0, leds[i].1));
+            }
+        }
+
+        leds.iter()
+            .zip(results)
+            .map(|((led, app), result)| result.unwrap_or_else(|| self.show(*led, app)))
+            .collect()
+    }
+
+    fn reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+
+    fn watch(&mut self, sender: Sender<Event>) {
+        particle_events::subscribe(
+            self.stream_client.clone(),
+            &self.api_url,
+            self.token.clone(),
+            self.device.clone(),
+            sender,
+        );
+    }
+}
+
+/// The `+flash` and `+alert` flags asking the firmware to flash the LED first, or to keep it
+/// blinking.
+fn flags(app: &App) -> String {
+    let mut flags = String::new();
+    if app.flash {
+        flags.push_str("+flash");
+    }
+    if app.blinking() {
+        flags.push_str("+alert");
+    }
+    flags
+}
+
+/// Whether a 404 from a function call means the firmware doesn't have the function.
+fn is_missing_function(message: &str) -> bool {
+    message.contains("Function") && message.contains("not found")
+}
+
+/// Splits the `setLeds` entries into batches (as indexes into `entries`) that each fit in one
+/// argument, skipping LEDs without an entry.
+fn batches(entries: &[Option<String>]) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    let mut arg_len = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let Some(entry) = entry else {
+            continue;
+        };
+        match batches.last_mut() {
+            Some(batch) if arg_len + 1 + entry.len() <= MAX_ARG_LEN => {
+                batch.push(i);
+                arg_len += 1 + entry.len();
+            }
+            _ => {
+                batches.push(vec![i]);
+                arg_len = entry.len();
+            }
+        }
+    }
+    batches
+}
+
+impl ParticleCloud {
+    /// The `<led>:<rrggbb>:<pattern>` argument for `app`'s status, if it has a configured style.
+    fn style(&self, led: u16, app: &App) -> Option<String> {
+        let style = self.styles.get(&app.last_status)?;
+        Some(format!(
+            "{}:{}:{}{}",
+            led,
+            style.color.hex(),
+            style.pattern.as_str(),
+            flags(app)
+        ))
+    }
+
+    /// Calls `function` on the device and returns its return value, once the answer is confirmed
+    /// to come from the configured, connected device.
+    fn call_function(&mut self, function: &str, arg: &str) -> Result<isize, ParticleError> {
+        let result = self
+            .check_version()
+            .and_then(|()| self.try_call_function(function, arg));
+        match &result {
+            Err(ParticleError::Disconnected) => self.disconnected = true,
+            Ok(_) if self.disconnected => {
+                self.disconnected = false;
+                self.reconnected = true;
+            }
+            _ => {}
+        }
+        result
+    }
+
+    /// Makes sure the firmware speaks `PROTOCOL_VERSION`, unless that is already known.
+    pub fn check_version(&mut self) -> Result<(), ParticleError> {
+        if self.version_checked {
+            return Ok(());
+        }
+
+        let url = format!(
+            "{}/v1/devices/{}/{}",
+            self.api_url, self.device, VERSION_VARIABLE
+        );
+        let response = self.client.get(url).bearer_auth(&self.token).send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let message = response
+                .json::<ParticleApiError>()
+                .map(|body| body.error)
+                .unwrap_or_else(|_| status.to_string());
+            // Firmware from before the handshake still has the first three per-status functions.
+            if status.as_u16() == 404 && message.contains("Variable not found") {
+                log!(
+                    "The firmware has no {} variable, assuming it predates the protocol handshake \
+                     and setting LEDs online, offline or undefined only",
+                    VERSION_VARIABLE
+                );
+                self.version_checked = true;
+                self.legacy = true;
+                return Ok(());
+            }
+            return Err(ParticleError::Api(status.as_u16(), message));
+        }
+
+        let version = response.json::<ParticleVariableResult>()?.result;
+        if version != PROTOCOL_VERSION {
+            return Err(ParticleError::Incompatible(version));
+        }
+        self.version_checked = true;
+        Ok(())
+    }
+
+    fn try_call_function(&self, function: &str, arg: &str) -> Result<isize, ParticleError> {
+        let url = format!("{}/v1/devices/{}/{}", self.api_url, self.device, function);
+
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(&self.token)
+            .json(&HashMap::from([("arg", arg)]))
+            .send()?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = response
+                .json::<ParticleApiError>()
+                .map(|body| body.error)
+                .unwrap_or_else(|_| status.to_string());
+            return Err(ParticleError::Api(status.as_u16(), message));
+        }
+
+        let result = response.json::<ParticleFnResult>()?;
+        if !result.connected {
+            return Err(ParticleError::Disconnected);
+        }
+        // The device can be configured by either its ID or its name.
+        if result.id != self.device && result.name != self.device {
+            return Err(ParticleError::WrongDevice {
+                id: result.id,
+                name: result.name,
+            });
+        }
+        Ok(result.return_value)
+    }
+}
+
+fn get_status_fn(status: &Status) -> String {
+    match status {
+        Status::Online => "setOnline",
+        Status::Offline => "setOffline",
+        Status::Errored => "setOffline",
+        Status::Unknown => "setUndefined",
+        Status::Failed => "setFailed",
+        Status::Activating => "setActivating",
+        Status::Deactivating => "setDeactivating",
+        Status::Reloading => "setReloading",
+        Status::Maintenance => "setMaintenance",
+        Status::Unreachable => "setUnreachable",
+        Status::Lost => "setLost",
+    }
+    .to_string()
+}
+
+/// The function for `status` on firmware from before the handshake, which shows failures as
+/// offline and anything in between as undefined.
+fn get_legacy_status_fn(status: &Status) -> String {
+    match status {
+        Status::Online => "setOnline",
+        Status::Offline | Status::Errored | Status::Failed => "setOffline",
+        Status::Unknown
+        | Status::Activating
+        | Status::Deactivating
+        | Status::Reloading
+        | Status::Maintenance
+        | Status::Unreachable
+        | Status::Lost => "setUndefined",
+    }
+    .to_string()
+}
@@ -0,0 +1,129 @@
+use crate::backoff::Backoff;
+use crate::Event;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::error::Error;
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// Published by the Particle Cloud whenever a device connects or disconnects.
+const STATUS_EVENT: &str = "spark/status";
+/// Published by the firmware when a button is pressed, with the button's number as its data.
+const BUTTON_EVENT: &str = "app-status/button";
+
+/// A Server-Sent Event from the Particle event stream.
+struct StreamEvent {
+    name: String,
+    data: String,
+}
+
+/// Follows `device`'s events on the Particle event stream in the background, reporting it going
+/// online and offline and its buttons being pressed to `sender`. The stream is reopened whenever
+/// it drops.
+pub fn subscribe(
+    client: Client,
+    api_url: &str,
+    token: String,
+    device: String,
+    sender: Sender<Event>,
+) {
+    let url = format!("{}/v1/devices/{}/events", api_url, device);
+
+    thread::spawn(move || {
+        let mut backoff = Backoff::default();
+        let mut connected_before = false;
+        loop {
+            let result = client
+                .get(&url)
+                .bearer_auth(&token)
+                .send()
+                .and_then(|response| response.error_for_status());
+            let error: Box<dyn Error> = match result {
+                Ok(response) => {
+                    backoff.reset();
+                    // Whatever happened to the device while the stream was down went unseen, so
+                    // it is treated like coming back.
+                    if connected_before && sender.send(Event::DeviceOnline(device.clone())).is_err()
+                    {
+                        return;
+                    }
+                    connected_before = true;
+
+                    let sent = read_events(BufReader::new(response), |event| {
+                        let data = event_data(&event.data);
+                        let event = match (event.name.as_str(), data.as_deref()) {
+                            (STATUS_EVENT, Some("online")) => Event::DeviceOnline(device.clone()),
+                            (STATUS_EVENT, Some("offline")) => Event::DeviceOffline(device.clone()),
+                            (BUTTON_EVENT, Some(button)) => match button.parse() {
+                                Ok(button) => Event::ButtonPressed(device.clone(), button),
+                                Err(_) => return true,
+                            },
+                            _ => return true,
+                        };
+                        sender.send(event).is_ok()
+                    });
+                    match sent {
+                        Ok(false) => return,
+                        Ok(true) => "the stream ended".into(),
+                        Err(error) => error.into(),
+                    }
+                }
+                Err(error) => error.into(),
+            };
+
+            let delay = backoff.fail();
+            log!(
+                "Lost the Particle event stream, reconnecting in {:.1}s: {}",
+                delay.as_secs_f32(),
+                error
+            );
+            thread::sleep(delay);
+        }
+    });
+}
+
+/// Passes each event in `reader` to `on_event` until the stream ends, or until `on_event` returns
+/// false, in which case this does too.
+fn read_events(
+    reader: impl BufRead,
+    mut on_event: impl FnMut(StreamEvent) -> bool,
+) -> std::io::Result<bool> {
+    let mut name = String::new();
+    let mut data = String::new();
+    for line in reader.lines() {
+        let line = line?;
+        // An empty line ends an event; lines starting with a colon are keep-alive comments.
+        if line.is_empty() {
+            if !data.is_empty() || !name.is_empty() {
+                let event = StreamEvent {
+                    name: std::mem::take(&mut name),
+                    data: std::mem::take(&mut data),
+                };
+                if !on_event(event) {
+                    return Ok(false);
+                }
+            }
+        } else if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim_start().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.trim_start());
+        }
+    }
+    Ok(true)
+}
+
+/// The `data` field of an event's JSON payload, e.g. "online" in
+/// `{"data":"online","ttl":60,"published_at":"...","coreid":"..."}`.
+fn event_data(payload: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Payload {
+        data: String,
+    }
+    serde_json::from_str::<Payload>(payload)
+        .ok()
+        .map(|payload| payload.data)
+}
@@ -0,0 +1,40 @@
+use super::Indicator;
+use crate::App;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+/// Writes `<led>:<status>` lines to a serial-attached LED controller, e.g. `3:failed`.
+pub struct Serial {
+    port: File,
+}
+
+impl Serial {
+    pub fn open(path: &Path, baud: Option<u32>) -> Result<Serial, Box<dyn Error>> {
+        if let Some(baud) = baud {
+            // Configuring the line once at startup is easier with stty than through termios.
+            let configured = Command::new("stty")
+                .arg("-F")
+                .arg(path)
+                .args([&baud.to_string(), "raw", "-echo"])
+                .status()?;
+            if !configured.success() {
+                return Err(format!("stty could not configure {}", path.display()).into());
+            }
+        }
+
+        Ok(Serial {
+            port: OpenOptions::new().write(true).open(path)?,
+        })
+    }
+}
+
+impl Indicator for Serial {
+    fn show(&mut self, led: u16, app: &App) -> Result<(), Box<dyn Error>> {
+        writeln!(self.port, "{}:{}", led, app.last_status.as_str())?;
+        self.port.flush()?;
+        Ok(())
+    }
+}
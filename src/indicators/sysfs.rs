@@ -0,0 +1,59 @@
+use super::Indicator;
+use crate::{App, Status};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+const SYSFS_LEDS: &str = "/sys/class/leds";
+
+/// Drives single color LEDs through the kernel's LED class, e.g. GPIO LEDs: online services light
+/// their LED, statuses in `blink` make it blink, and anything else turns it off.
+pub struct Sysfs {
+    leds: HashMap<u16, PathBuf>,
+    blink: Vec<Status>,
+}
+
+impl Sysfs {
+    /// `leds` maps LED numbers to LED class devices, either by name or by full path.
+    pub fn new(leds: &HashMap<String, String>, blink: &[Status]) -> Sysfs {
+        Sysfs {
+            // The config is validated, so every number parses.
+            leds: leds
+                .iter()
+                .filter_map(|(led, device)| Some((led.parse().ok()?, led_path(device))))
+                .collect(),
+            blink: blink.to_vec(),
+        }
+    }
+}
+
+impl Indicator for Sysfs {
+    fn show(&mut self, led: u16, app: &App) -> Result<(), Box<dyn Error>> {
+        let path = self
+            .leds
+            .get(&led)
+            .ok_or_else(|| format!("no sysfs LED is configured for LED {}", led))?;
+
+        if self.blink.contains(&app.last_status) {
+            fs::write(path.join("trigger"), "timer")?;
+            return Ok(());
+        }
+
+        fs::write(path.join("trigger"), "none")?;
+        let brightness = match app.last_status {
+            Status::Online => fs::read_to_string(path.join("max_brightness"))?,
+            _ => "0".to_string(),
+        };
+        fs::write(path.join("brightness"), brightness.trim())?;
+        Ok(())
+    }
+}
+
+fn led_path(device: &str) -> PathBuf {
+    if device.starts_with('/') {
+        PathBuf::from(device)
+    } else {
+        PathBuf::from(SYSFS_LEDS).join(device)
+    }
+}
@@ -0,0 +1,76 @@
+use super::Indicator;
+use crate::{App, Status};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::atomic::Ordering;
+
+/// Redraws a table of every LED on the terminal, for running without any hardware. Logs go to
+/// stderr meanwhile, so they can be sent elsewhere rather than scroll the table away.
+pub struct Terminal {
+    leds: BTreeMap<u16, (String, Status, bool)>,
+}
+
+impl Terminal {
+    pub fn new() -> Terminal {
+        crate::LOG_TO_STDERR.store(true, Ordering::Relaxed);
+        Terminal {
+            leds: BTreeMap::new(),
+        }
+    }
+
+    fn set(&mut self, led: u16, app: &App) {
+        self.leds
+            .insert(led, (app.name.clone(), app.last_status, app.blinking()));
+    }
+
+    fn draw(&self) -> Result<(), Box<dyn Error>> {
+        let mut out = std::io::stdout().lock();
+        // Clears the screen and moves the cursor to the top left.
+        write!(out, "\x1b[2J\x1b[H")?;
+        for (led, (name, status, blinking)) in &self.leds {
+            // Alerts blink, on terminals that support it.
+            writeln!(
+                out,
+                "{:>4}  \x1b[{}{}m\u{25cf}\x1b[0m  {:<12}  {}",
+                led,
+                if *blinking { "5;" } else { "" },
+                color(status),
+                status.as_str(),
+                name
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Indicator for Terminal {
+    fn show(&mut self, led: u16, app: &App) -> Result<(), Box<dyn Error>> {
+        self.set(led, app);
+        self.draw()
+    }
+
+    /// Redraws once for the whole batch rather than once per LED.
+    fn show_all(&mut self, leds: &[(u16, &App)]) -> Vec<Result<(), Box<dyn Error>>> {
+        for (led, app) in leds {
+            self.set(*led, app);
+        }
+        let drawn = self.draw().map_err(|error| error.to_string());
+        leds.iter()
+            .map(|_| drawn.clone().map_err(Into::into))
+            .collect()
+    }
+}
+
+/// The ANSI color code for `status`.
+fn color(status: &Status) -> u8 {
+    match status {
+        Status::Online => 32,
+        Status::Offline | Status::Errored | Status::Failed => 31,
+        Status::Activating | Status::Deactivating | Status::Reloading => 33,
+        Status::Maintenance => 34,
+        Status::Unreachable | Status::Lost => 35,
+        Status::Unknown => 37,
+    }
+}
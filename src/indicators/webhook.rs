@@ -0,0 +1,54 @@
+use super::Indicator;
+use crate::{App, Status};
+use reqwest::blocking::Client;
+use serde::Serialize;
+use std::error::Error;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Updates are shown from the main loop, so a slow receiver must not hold it up for long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs every LED change as JSON to a URL.
+pub struct Webhook {
+    url: String,
+    device: String,
+    client: Client,
+}
+
+#[derive(Serialize)]
+struct LedChange<'a> {
+    device: &'a str,
+    led: u16,
+    service: &'a str,
+    status: Status,
+}
+
+impl Webhook {
+    pub fn new(url: String, device: String) -> reqwest::Result<Webhook> {
+        Ok(Webhook {
+            url,
+            device,
+            client: Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(TIMEOUT)
+                .build()?,
+        })
+    }
+}
+
+impl Indicator for Webhook {
+    fn show(&mut self, led: u16, app: &App) -> Result<(), Box<dyn Error>> {
+        self.client
+            .post(&self.url)
+            .json(&LedChange {
+                device: &self.device,
+                led,
+                service: &app.name,
+                status: app.last_status,
+            })
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
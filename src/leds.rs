@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Hands out LEDs to services, keeping pinned LEDs reserved and remembering (in a state file)
+/// which LED every unpinned service had, so it gets the same one back after a restart.
+pub struct LedAllocator {
+    available: Vec<u16>,
+    pinned: HashMap<String, u16>,
+    /// Units whose LED isn't remembered, as they come and go, until they give it back.
+    forgotten: HashSet<String>,
+    state: LedState,
+    state_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LedState {
+    #[serde(default)]
+    leds: BTreeMap<String, u16>,
+}
+
+impl LedAllocator {
+    pub fn new(
+        leds: impl Iterator<Item = u16>,
+        pinned: HashMap<String, u16>,
+        state_path: PathBuf,
+    ) -> LedAllocator {
+        let state = match std::fs::read_to_string(&state_path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|error| {
+                log!(
+                    "Ignoring unreadable LED state in {}: {}",
+                    state_path.display(),
+                    error
+                );
+                LedState::default()
+            }),
+            Err(_) => LedState::default(),
+        };
+
+        LedAllocator {
+            available: leds
+                .filter(|led| !pinned.values().any(|pinned| pinned == led))
+                .collect(),
+            pinned,
+            forgotten: HashSet::new(),
+            state,
+            state_path,
+        }
+    }
+
+    /// The LED `unit` should use: its pinned LED, the LED it had last time if that is free,
+    /// otherwise the lowest free LED that no other service remembers.
+    pub fn assign(&mut self, unit: &str) -> Option<u16> {
+        if let Some(led) = self.pinned.get(unit) {
+            return Some(*led);
+        }
+
+        let remembered = self.state.leds.get(unit).copied();
+        let led = remembered
+            .filter(|led| self.available.contains(led))
+            .or_else(|| {
+                self.available
+                    .iter()
+                    .filter(|led| !self.state.leds.values().any(|other| other == *led))
+                    .min()
+                    .copied()
+            })
+            .or_else(|| self.available.iter().min().copied())?;
+
+        self.available.retain(|available| *available != led);
+        if remembered != Some(led) {
+            self.hand_over(unit, led);
+        }
+        Some(led)
+    }
+
+    /// Records that `unit` now has `led`, which was taken from another service.
+    pub fn hand_over(&mut self, unit: &str, led: u16) {
+        if self.forgotten.contains(unit) {
+            return;
+        }
+        // Whoever remembered this LED before loses it, so they don't fight over it later.
+        self.state.leds.retain(|_, other| *other != led);
+        self.state.leds.insert(unit.to_string(), led);
+        self.save();
+    }
+
+    /// Doesn't remember which LED `unit` gets, e.g. for units matched by a pattern, which would
+    /// otherwise fill the state file with every template instance ever seen.
+    pub fn forget(&mut self, unit: &str) {
+        self.forgotten.insert(unit.to_string());
+        if self.state.leds.remove(unit).is_some() {
+            self.save();
+        }
+    }
+
+    pub fn is_pinned(&self, unit: &str) -> bool {
+        self.pinned.contains_key(unit)
+    }
+
+    /// Lets go of `unit`, which went away, returning its LED, if it had one, to the pool. `unit`
+    /// keeps its claim on the LED until someone else needs it.
+    pub fn release(&mut self, unit: &str, led: Option<u16>) {
+        self.forgotten.remove(unit);
+        if let Some(led) = led {
+            if !self.pinned.contains_key(unit) && !self.available.contains(&led) {
+                self.available.push(led);
+            }
+        }
+    }
+
+    fn save(&self) {
+        let result = toml::to_string(&self.state)
+            .map_err(|error| error.to_string())
+            .and_then(|contents| {
+                std::fs::write(&self.state_path, contents).map_err(|error| error.to_string())
+            });
+        if let Err(error) = result {
+            log!(
+                "Could not save LED state to {}: {}",
+                self.state_path.display(),
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An allocator for `leds` with its state in a scratch file named after the test.
+    fn allocator(name: &str, leds: &[u16], pinned: &[(&str, u16)]) -> LedAllocator {
+        LedAllocator::new(
+            leds.iter().copied(),
+            pinned
+                .iter()
+                .map(|(unit, led)| (unit.to_string(), *led))
+                .collect(),
+            state_path(name),
+        )
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "app_status_leds_{}_{}.toml",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn fresh(name: &str) {
+        let _ = std::fs::remove_file(state_path(name));
+    }
+
+    #[test]
+    fn hands_out_the_lowest_free_led() {
+        fresh("lowest");
+        let mut leds = allocator("lowest", &[2, 3, 5], &[]);
+        assert_eq!(leds.assign("a"), Some(2));
+        assert_eq!(leds.assign("b"), Some(3));
+        assert_eq!(leds.assign("c"), Some(5));
+        assert_eq!(leds.assign("d"), None);
+    }
+
+    #[test]
+    fn pinned_leds_are_kept_for_their_service() {
+        fresh("pinned");
+        let mut leds = allocator("pinned", &[1, 2, 3], &[("a", 1), ("z", 11)]);
+        assert_eq!(leds.assign("b"), Some(2));
+        assert_eq!(leds.assign("a"), Some(1));
+        // Pins may be outside the assignable LEDs, e.g. on a reserved one.
+        assert_eq!(leds.assign("z"), Some(11));
+        assert_eq!(leds.assign("c"), Some(3));
+        assert_eq!(leds.assign("d"), None);
+    }
+
+    #[test]
+    fn services_get_their_led_back_after_a_restart() {
+        fresh("restart");
+        let mut leds = allocator("restart", &[1, 2, 3], &[]);
+        assert_eq!(leds.assign("a"), Some(1));
+        assert_eq!(leds.assign("b"), Some(2));
+
+        let mut leds = allocator("restart", &[1, 2, 3], &[]);
+        assert_eq!(leds.assign("b"), Some(2));
+        // A new service doesn't take the LED another one remembers.
+        assert_eq!(leds.assign("c"), Some(3));
+        assert_eq!(leds.assign("a"), Some(1));
+    }
+
+    #[test]
+    fn released_leds_go_to_their_service_first() {
+        fresh("release");
+        let mut leds = allocator("release", &[1, 2], &[]);
+        assert_eq!(leds.assign("a"), Some(1));
+        assert_eq!(leds.assign("b"), Some(2));
+        leds.release("a", Some(1));
+        assert_eq!(leds.assign("a"), Some(1));
+    }
+
+    #[test]
+    fn forgotten_units_are_not_remembered() {
+        fresh("forget");
+        let mut leds = allocator("forget", &[1, 2, 3], &[]);
+        assert_eq!(leds.assign("a"), Some(1));
+        leds.forget("worker@1");
+        assert_eq!(leds.assign("worker@1"), Some(2));
+
+        let mut leds = allocator("forget", &[1, 2, 3], &[]);
+        assert_eq!(leds.assign("b"), Some(2));
+        assert_eq!(leds.assign("a"), Some(1));
+    }
+}
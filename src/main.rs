@@ -1,184 +1,273 @@
+use backoff::Backoff;
+use config::{Config, Mode, Scope, ServiceConfig};
 use core::time::Duration;
+use device::Device;
 use dotenv::dotenv;
-use serde::Deserialize;
+use poller::Poller;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::env;
-use std::io::{Error, ErrorKind, Read};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use systemd::Managers;
 
-#[derive(PartialEq, Debug)]
+/// Set while the terminal indicator draws its table on stdout, so logs don't write over it.
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Prints a line to the log, which is stdout unless the terminal indicator has that.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if crate::LOG_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod agent;
+mod backoff;
+mod buttons;
+mod config;
+mod device;
+mod indicators;
+mod leds;
+mod overflow;
+mod poller;
+mod server;
+mod sources;
+mod systemd;
+
+/// How often statuses are polled when unit changes can't be subscribed to.
+const POLL_INTERVAL: Duration = Duration::from_secs(4);
+/// How often statuses are polled anyway while subscribed, in case a signal is missed.
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Sent with every request to the Particle Cloud and to the server, e.g. `app_status_rust/0.1.0`.
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 enum Status {
     Online,
     Offline,
     Errored,
     Unknown,
+    Failed,
+    Activating,
+    Deactivating,
+    Reloading,
+    Maintenance,
+    /// The host the service runs on couldn't be reached, so its state isn't known.
+    Unreachable,
+    /// The agent reporting the service stopped reporting, so its state isn't known.
+    Lost,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Online => "online",
+            Status::Offline => "offline",
+            Status::Errored => "errored",
+            Status::Unknown => "unknown",
+            Status::Failed => "failed",
+            Status::Activating => "activating",
+            Status::Deactivating => "deactivating",
+            Status::Reloading => "reloading",
+            Status::Maintenance => "maintenance",
+            Status::Unreachable => "unreachable",
+            Status::Lost => "lost",
+        }
+    }
+
+    /// How bad a status is, for showing the worst of several services on one LED.
+    pub fn severity(&self) -> u8 {
+        match self {
+            Status::Online => 0,
+            Status::Unknown => 1,
+            Status::Reloading => 2,
+            Status::Activating | Status::Deactivating => 3,
+            Status::Maintenance => 4,
+            Status::Unreachable | Status::Lost => 5,
+            Status::Offline => 6,
+            Status::Errored => 7,
+            Status::Failed => 8,
+        }
+    }
+}
+
+/// Something the main loop is woken up for.
+#[derive(Debug)]
+enum Event {
+    /// A watched service changed state: a local systemd unit, or one an agent reports.
+    ServiceChanged(String),
+    /// The named device connected, e.g. after a reboot, and may have lost its LEDs.
+    DeviceOnline(String),
+    /// The named device disconnected, so there is no point in sending it updates.
+    DeviceOffline(String),
+    /// A button on the named device was pressed, numbered from 1.
+    ButtonPressed(String, u8),
 }
 
 #[derive(Debug)]
 struct App {
     pub name: String,
+    /// The latest status seen, which the LED should show.
     pub last_status: Status,
-    pub led_num: Option<i8>,
+    /// The status the device last acknowledged showing, if any.
+    pub confirmed_status: Option<Status>,
+    pub led_num: Option<u16>,
+    pub functions: HashMap<Status, String>,
+    pub priority: i32,
+    /// Spaces out retries while the device isn't taking updates.
+    pub backoff: Backoff,
+    /// Whether the LED should flash before showing `last_status`, because it just changed.
+    pub flash: bool,
+    /// Whether the LED keeps blinking until someone acknowledges it.
+    pub alerting: bool,
+    /// Whether alerts are muted, so the LED doesn't blink or flash for now.
+    pub muted: bool,
 }
 
 impl App {
-    pub fn new(name: String, last_status: Status, led_num: Option<i8>) -> App {
+    pub fn new(name: String, last_status: Status, led_num: Option<u16>) -> App {
         App {
             name,
             last_status,
+            confirmed_status: None,
             led_num,
+            functions: HashMap::new(),
+            priority: 0,
+            backoff: Backoff::default(),
+            flash: false,
+            alerting: false,
+            muted: false,
         }
     }
-}
 
-#[derive(Deserialize, Debug)]
-struct ParticleFnResult {
-    id: String,
-    name: String,
-    connected: bool,
-    return_value: isize,
+    /// Whether the LED should be blinking for an alert.
+    pub fn blinking(&self) -> bool {
+        self.alerting && !self.muted
+    }
+
+    /// Whether the LED doesn't show `last_status` yet.
+    pub fn needs_sync(&self) -> bool {
+        self.led_num.is_some() && self.confirmed_status != Some(self.last_status)
+    }
+
+    /// An app for `id`, which is `service` itself or one of the units its pattern matches.
+    pub fn from_service(
+        service: &ServiceConfig,
+        id: &str,
+        last_status: Status,
+        led_num: Option<u16>,
+    ) -> App {
+        let name = if service.is_pattern() {
+            id.to_string()
+        } else {
+            service.display_name().to_string()
+        };
+        App {
+            functions: service.functions.clone(),
+            priority: service.priority,
+            ..App::new(name, last_status, led_num)
+        }
+    }
 }
 
 fn main() {
     dotenv().ok();
 
-    let token = env::var("ACCESS_TOKEN").expect("Please provide a Particle access token!");
-
-    let mut app_statuses: HashMap<String, App> = HashMap::new();
-    let mut available_led_nums: Vec<i8> = (1..12).collect();
+    let (mode, path) = config::args();
+    let config = Config::load(&path, mode).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    let (sender, receiver) = mpsc::channel();
 
-    loop {
-        std::thread::sleep(Duration::from_secs(4));
-
-        let status_map = get_statuses();
-
-        // Frees up an LED if a process status is no longer present.
-        let app_names: Vec<String> = app_statuses.keys().cloned().collect();
-        for app_name in app_names {
-            if !status_map.iter().any(|(name, _)| name == &app_name) {
-                if let Some((
-                    _,
-                    App {
-                        led_num: Some(led), ..
-                    },
-                )) = app_statuses.remove_entry(&app_name)
-                {
-                    available_led_nums.push(led);
-                }
-            }
-        }
+    if let (Mode::Agent, Some(agent)) = (mode, &config.agent) {
+        let poller = start_poller(&config, sender, None);
+        agent::run(agent, poller, receiver);
+    }
+    let reports = match (mode, &config.server) {
+        (Mode::Server, Some(server)) => Some(
+            server::listen(server, sender.clone()).unwrap_or_else(|error| {
+                eprintln!("Could not listen on {}: {}", server.listen, error);
+                std::process::exit(1);
+            }),
+        ),
+        _ => None,
+    };
 
-        // Iterate through the list of processes and turn on LEDs to reflect their state.
-        for (app_name, status) in status_map {
-            let app = app_statuses.entry(app_name.clone()).or_insert(App::new(
-                app_name,
-                Status::Unknown,
-                available_led_nums.pop(),
-            ));
+    let mut devices: Vec<Device> = config
+        .devices
+        .iter()
+        .map(|device| Device::new(&config, device))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        });
 
-            if app.last_status != status {
-                update_app(&token, app, status)
-            }
-        }
+    for device in &mut devices {
+        device.watch(sender.clone());
     }
-}
+    let mut poller = start_poller(&config, sender, reports.as_ref());
 
-fn update_app(token: &String, app: &mut App, new_status: Status) {
-    app.last_status = new_status;
-    if let (Some(led), Ok(device)) = (app.led_num, env::var("DEVICE_NAME")) {
-        let to_call = get_status_fn(&app.last_status);
-        let url = format!("https://api.particle.io/v1/devices/{}/{}", device, to_call);
-
-        // Apparently I have to create the client every time, because you can't change the URL after creation.....
-        let client = reqwest::blocking::Client::new()
-            .post(url)
-            .bearer_auth(token)
-            .json(&HashMap::from([("arg", format!("{}", led))]));
-
-        if let Err(error) = client
-            .send()
-            .and_then(|resp| resp.json::<ParticleFnResult>())
-            .and_then(|result| {
-                println!("Successfully updated LED {}", result.return_value);
-                Ok(())
-            })
-        {
-            println!("Error when calling Particle Cloud fn: {:?}", error);
+    loop {
+        // Services are polled once, however many devices show them.
+        let statuses = poller.get_statuses();
+        let mut timeout = poller.until_next_poll();
+        for device in &mut devices {
+            device.update(&config, &statuses);
+            timeout = timeout.min(device.until_next_update());
         }
-    }
-}
-
-fn get_status_fn(status: &Status) -> String {
-    match status {
-        Status::Online => "setOnline",
-        Status::Offline => "setOffline",
-        Status::Errored => "setOffline",
-        Status::Unknown => "setUndefined",
-    }
-    .to_string()
-}
 
-fn get_statuses() -> Vec<(String, Status)> {
-    env::var("APPS")
-        .unwrap_or(String::new())
-        .split(",")
-        .filter_map(|service_name| {
-            if let Ok(capture) = systemctl_capture(vec!["status", service_name]) {
-                Some((
-                    service_name.to_string(),
-                    systemctl_capture_to_status(capture),
-                ))
-            } else {
-                None
+        // Wakes up as soon as a watched service changes state, a device comes or goes, or a button is
+        // pressed, otherwise when the next poll is due.
+        match receiver.recv_timeout(timeout) {
+            Ok(event) => {
+                for event in std::iter::once(event).chain(receiver.try_iter()) {
+                    match &event {
+                        Event::ServiceChanged(id) => poller.poll_now(id),
+                        Event::DeviceOnline(name)
+                        | Event::DeviceOffline(name)
+                        | Event::ButtonPressed(name, _) => {
+                            if let Some(device) =
+                                devices.iter_mut().find(|device| &device.name == name)
+                            {
+                                device.handle(&event);
+                            }
+                        }
+                    }
+                }
             }
-        })
-        .collect()
-}
-
-fn systemctl_capture_to_status(capture: String) -> Status {
-    // need to change this to be more accurate. Need to find out if "inactive" changes when it's offline out of an error.
-    if capture.contains("Active: active") {
-        Status::Online
-    } else if capture.contains("Active: inactive") {
-        Status::Offline
-    } else {
-        Status::Errored
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+        }
     }
 }
 
-// from https://docs.rs/systemctl/latest/src/systemctl/lib.rs.html#22-58
-/// Invokes `systemctl $args` and captures stdout stream
-fn systemctl_capture(args: Vec<&str>) -> std::io::Result<String> {
-    let mut child = std::process::Command::new("/usr/bin/systemctl")
-        .args(args.clone())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::null())
-        .spawn()?;
-    let _exitcode = child.wait()?;
-    //TODO improve this please
-    //Interrogating some services returns an error code
-    //match exitcode.success() {
-    //true => {
-    let mut stdout: Vec<u8> = Vec::new();
-    if let Ok(size) = child.stdout.unwrap().read_to_end(&mut stdout) {
-        if size > 0 {
-            if let Ok(s) = String::from_utf8(stdout) {
-                Ok(s)
-            } else {
-                Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Invalid utf8 data in stdout",
-                ))
-            }
-        } else {
-            Err(Error::new(ErrorKind::InvalidData, "systemctl stdout empty"))
+/// Sets up polling of every service, subscribing to changes of local systemd units where possible.
+fn start_poller(
+    config: &Config,
+    sender: Sender<Event>,
+    reports: Option<&server::Reports>,
+) -> Poller {
+    // Only managers with units to watch are connected to, so other checks also work where the
+    // D-Bus API isn't available.
+    let mut units: HashMap<Scope, Vec<(String, String)>> = HashMap::new();
+    for service in &config.services {
+        // Units matching a pattern are looked for on every poll instead.
+        if let (Some(unit), false) = (service.local_unit(), service.is_pattern()) {
+            units
+                .entry(service.scope.clone())
+                .or_default()
+                .push((unit.to_string(), service.id().to_string()));
         }
-    } else {
-        Err(Error::new(ErrorKind::InvalidData, "systemctl stdout empty"))
     }
-    /*},
-        false => {
-            Err(Error::new(ErrorKind::Other,
-                format!("/usr/bin/systemctl {:?} failed", args)))
-        }
-    }*/
+    let managers = Managers::connect(&units, sender.clone()).unwrap_or_else(|error| {
+        eprintln!("Could not connect to the systemd D-Bus API: {}", error);
+        std::process::exit(1);
+    });
+    Poller::new(config, &managers, reports, sender)
 }
@@ -0,0 +1,151 @@
+use crate::config::{OverflowConfig, OverflowPolicy};
+use crate::{App, Status};
+use std::time::{Duration, Instant};
+
+const DEFAULT_PAGE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shows the services that didn't get an LED of their own on a single shared LED.
+pub struct Overflow {
+    policy: OverflowPolicy,
+    page_interval: Duration,
+    page: usize,
+    next_page: Instant,
+    /// How many services overflowed last time, as there is nothing to page through with fewer
+    /// than two.
+    overflowed: usize,
+    /// The shared LED, tracking what it currently shows so it is only updated on changes.
+    pub app: App,
+}
+
+impl Overflow {
+    pub fn new(config: &OverflowConfig) -> Option<Overflow> {
+        if config.policy == OverflowPolicy::None {
+            return None;
+        }
+
+        Some(Overflow {
+            policy: config.policy,
+            page_interval: config
+                .page_interval
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_PAGE_INTERVAL),
+            page: 0,
+            next_page: Instant::now(),
+            overflowed: 0,
+            app: App::new("overflow".to_string(), Status::Unknown, config.led),
+        })
+    }
+
+    /// The status the shared LED should show for the apps in `overflowed`.
+    pub fn status(&mut self, overflowed: &mut [&App]) -> Status {
+        self.overflowed = overflowed.len();
+        if overflowed.is_empty() {
+            return Status::Unknown;
+        }
+        overflowed.sort_by(|a, b| a.name.cmp(&b.name));
+
+        match self.policy {
+            OverflowPolicy::Pager => {
+                let now = Instant::now();
+                if now >= self.next_page {
+                    self.page = self.page.wrapping_add(1);
+                    self.next_page = now + self.page_interval;
+                }
+                let shown = overflowed[self.page % overflowed.len()];
+                self.app.name = format!("overflow ({})", shown.name);
+                shown.last_status
+            }
+            OverflowPolicy::Aggregate | OverflowPolicy::None => overflowed
+                .iter()
+                .map(|app| app.last_status)
+                .max_by_key(Status::severity)
+                .unwrap_or(Status::Unknown),
+        }
+    }
+
+    /// How long until the pager moves on to the next service, if it has more than one to show.
+    pub fn until_next_page(&self) -> Option<Duration> {
+        match self.policy {
+            OverflowPolicy::Pager if self.overflowed > 1 => {
+                Some(self.next_page.saturating_duration_since(Instant::now()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overflow(policy: OverflowPolicy) -> Overflow {
+        Overflow::new(&OverflowConfig {
+            policy,
+            led: Some(11),
+            page_interval: Some(60),
+        })
+        .unwrap()
+    }
+
+    fn apps() -> Vec<App> {
+        vec![
+            App::new("b".to_string(), Status::Failed, None),
+            App::new("a".to_string(), Status::Online, None),
+            App::new("c".to_string(), Status::Offline, None),
+        ]
+    }
+
+    #[test]
+    fn no_policy_has_no_overflow() {
+        assert!(Overflow::new(&OverflowConfig::default()).is_none());
+    }
+
+    #[test]
+    fn aggregate_shows_the_worst_status() {
+        let mut overflow = overflow(OverflowPolicy::Aggregate);
+        let apps = apps();
+        let mut overflowed: Vec<&App> = apps.iter().collect();
+        assert_eq!(overflow.status(&mut overflowed), Status::Failed);
+        assert_eq!(overflow.until_next_page(), None);
+        assert_eq!(overflow.status(&mut []), Status::Unknown);
+    }
+
+    #[test]
+    fn pager_takes_turns_by_name() {
+        let mut overflow = overflow(OverflowPolicy::Pager);
+        let apps = apps();
+        let mut overflowed: Vec<&App> = apps.iter().collect();
+
+        let mut shown = Vec::new();
+        for _ in 0..4 {
+            // Turns the page on every call.
+            overflow.next_page = Instant::now();
+            overflow.status(&mut overflowed);
+            shown.push(overflow.app.name.clone());
+        }
+        assert_eq!(
+            shown,
+            [
+                "overflow (b)",
+                "overflow (c)",
+                "overflow (a)",
+                "overflow (b)"
+            ]
+        );
+        // The page stays until its interval is up.
+        assert_eq!(overflow.status(&mut overflowed), Status::Failed);
+        assert!(overflow.until_next_page().unwrap() > Duration::from_secs(50));
+    }
+
+    #[test]
+    fn pager_without_enough_services_doesnt_wake_up() {
+        let mut overflow = overflow(OverflowPolicy::Pager);
+        assert_eq!(overflow.until_next_page(), None);
+        assert_eq!(overflow.status(&mut []), Status::Unknown);
+        assert_eq!(overflow.until_next_page(), None);
+
+        let apps = apps();
+        assert_eq!(overflow.status(&mut [&apps[0]]), Status::Failed);
+        assert_eq!(overflow.until_next_page(), None);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::config::Config;
+use crate::server::Reports;
+use crate::sources::{self, PatternSource, SshHosts, StatusSource};
+use crate::systemd::{Managers, Subscription};
+use crate::{Event, Status};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+/// Polls each configured service on its own interval, remembering the last status of services
+/// that aren't due yet.
+pub struct Poller {
+    services: Vec<PolledService>,
+    next_poll: HashMap<String, Instant>,
+    last_statuses: HashMap<String, Status>,
+}
+
+struct PolledService {
+    id: String,
+    interval: Duration,
+    /// While subscribed, changes are reported as they happen and this longer interval is used.
+    subscribed: Option<(Subscription, Duration)>,
+    source: Source,
+}
+
+impl PolledService {
+    fn interval(&self) -> Duration {
+        match &self.subscribed {
+            Some((subscription, interval)) if subscription.is_active() => *interval,
+            _ => self.interval,
+        }
+    }
+}
+
+enum Source {
+    Service(Box<dyn StatusSource>),
+    /// A unit pattern, with the ID and status of every unit it matched on the last poll.
+    Pattern {
+        source: PatternSource,
+        id_prefix: String,
+        matched: Vec<(String, Status)>,
+    },
+}
+
+impl Poller {
+    /// Local systemd units are polled every `FALLBACK_POLL_INTERVAL` by default while their manager
+    /// is subscribed to; everything else, including unit patterns, every `POLL_INTERVAL`. Checks
+    /// of units on other hosts send `events` when they find a change.
+    pub fn new(
+        config: &Config,
+        managers: &Managers,
+        reports: Option<&Reports>,
+        events: Sender<Event>,
+    ) -> Poller {
+        let ssh_hosts = SshHosts::new(events);
+        Poller {
+            services: config
+                .services
+                .iter()
+                .filter_map(|service| {
+                    // Changes to units on other machines can't be subscribed to, and units
+                    // matching a pattern have to be looked for.
+                    let subscribed = match service.local_unit() {
+                        Some(_) if !service.is_pattern() => {
+                            managers.subscription(&service.scope).map(|subscription| {
+                                let interval =
+                                    config.poll_interval(service, crate::FALLBACK_POLL_INTERVAL);
+                                (subscription, interval)
+                            })
+                        }
+                        _ => None,
+                    };
+                    let source = if service.is_pattern() {
+                        Source::Pattern {
+                            source: sources::pattern_from_config(service, managers)?,
+                            id_prefix: service.id_prefix(),
+                            matched: Vec::new(),
+                        }
+                    } else {
+                        Source::Service(sources::from_config(
+                            service, config, managers, reports, &ssh_hosts,
+                        )?)
+                    };
+                    Some(PolledService {
+                        id: service.id().to_string(),
+                        interval: config.poll_interval(service, crate::POLL_INTERVAL),
+                        subscribed,
+                        source,
+                    })
+                })
+                .collect(),
+            next_poll: HashMap::new(),
+            last_statuses: HashMap::new(),
+        }
+    }
+
+    /// Makes `id` due immediately, e.g. because systemd reported it changed.
+    pub fn poll_now(&mut self, id: &str) {
+        self.next_poll.remove(id);
+    }
+
+    /// How long until the next service is due.
+    pub fn until_next_poll(&self) -> Duration {
+        let now = Instant::now();
+        self.services
+            .iter()
+            .map(|service| match self.next_poll.get(&service.id) {
+                Some(next) => next.saturating_duration_since(now),
+                None => Duration::ZERO,
+            })
+            .min()
+            .unwrap_or(crate::FALLBACK_POLL_INTERVAL)
+    }
+
+    /// Polls the services that are due and returns the latest known status of every service
+    /// that could be read.
+    pub fn get_statuses(&mut self) -> Vec<(String, Status)> {
+        let now = Instant::now();
+        for service in &mut self.services {
+            if self
+                .next_poll
+                .get(&service.id)
+                .is_some_and(|next| *next > now)
+            {
+                continue;
+            }
+            self.next_poll
+                .insert(service.id.clone(), now + service.interval());
+
+            match &mut service.source {
+                Source::Service(source) => match source.status() {
+                    Ok(status) => {
+                        self.last_statuses.insert(service.id.clone(), status);
+                    }
+                    Err(error) => {
+                        log!("Could not read the state of {}: {}", service.id, error);
+                        self.last_statuses.remove(&service.id);
+                    }
+                },
+                // Units that no longer match drop out, which frees their LEDs.
+                Source::Pattern {
+                    source,
+                    id_prefix,
+                    matched,
+                } => match source.statuses() {
+                    Ok(statuses) => {
+                        *matched = statuses
+                            .into_iter()
+                            .map(|(unit, status)| (format!("{}{}", id_prefix, unit), status))
+                            .collect();
+                    }
+                    Err(error) => {
+                        log!(
+                            "Could not list the units matching {}: {}",
+                            service.id,
+                            error
+                        );
+                        matched.clear();
+                    }
+                },
+            }
+        }
+
+        // A unit matched by a pattern may also be listed on its own, or matched by another pattern,
+        // but is only reported once.
+        let mut seen = HashSet::new();
+        self.services
+            .iter()
+            .flat_map(|service| match &service.source {
+                Source::Service(_) => self
+                    .last_statuses
+                    .get(&service.id)
+                    .map(|status| vec![(service.id.clone(), *status)])
+                    .unwrap_or_default(),
+                Source::Pattern { matched, .. } => matched.clone(),
+            })
+            .filter(|(id, _)| seen.insert(id.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,250 @@
+use crate::config::{RemoteAgentConfig, ServerConfig};
+use crate::{Event, Status};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where agents POST their reports.
+pub const REPORT_PATH: &str = "/v1/report";
+/// Reports are small, so anything bigger is refused rather than read.
+const MAX_BODY_LEN: usize = 1 << 20;
+/// The request line and headers are small too; longer ones are refused once this much is read.
+const MAX_HEADER_LEN: u64 = 8 << 10;
+/// How long a connection may take to send its whole request, however slowly it trickles in.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connections handled at once; more are turned away until one finishes. A few per agent is plenty.
+const MAX_CONNECTIONS: usize = 32;
+
+/// What an agent sends: the latest status of every service it could check, by the service's id
+/// on the agent.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusReport {
+    pub agent: String,
+    pub statuses: HashMap<String, Status>,
+}
+
+/// The latest report of every agent, shared between the listener and the services' sources.
+#[derive(Clone)]
+pub struct Reports {
+    started: Instant,
+    agents: Arc<Mutex<HashMap<String, Received>>>,
+}
+
+struct Received {
+    at: Instant,
+    statuses: HashMap<String, Status>,
+}
+
+impl Reports {
+    /// The status `agent` last reported for `service`: `Lost` once the agent has been quiet for
+    /// `timeout`, `Unknown` until it reports for the first time, and `None` if it doesn't report
+    /// the service.
+    pub fn status(&self, agent: &str, service: &str, timeout: Duration) -> Option<Status> {
+        match self.agents.lock().unwrap().get(agent) {
+            Some(received) if received.at.elapsed() < timeout => {
+                received.statuses.get(service).copied()
+            }
+            Some(_) => Some(Status::Lost),
+            None if self.started.elapsed() < timeout => Some(Status::Unknown),
+            None => Some(Status::Lost),
+        }
+    }
+}
+
+/// Accepts reports from the agents in `config` on a background thread, sending
+/// `Event::ServiceChanged` with the server-side id of every service whose status changed.
+pub fn listen(config: &ServerConfig, sender: Sender<Event>) -> io::Result<Reports> {
+    let listener = TcpListener::bind(&config.listen)?;
+    let reports = Reports {
+        started: Instant::now(),
+        agents: Arc::default(),
+    };
+    let agents = Arc::new(config.agents.clone());
+
+    let shared = reports.clone();
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                respond(stream, "503 Service Unavailable").ok();
+                continue;
+            }
+            let reports = shared.clone();
+            let agents = agents.clone();
+            let sender = sender.clone();
+            let connections = connections.clone();
+            // One thread per connection, so an agent on a bad link doesn't hold up the others.
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_default();
+                if let Err(error) = handle(stream, &reports, &agents, &sender) {
+                    log!("Could not read a report from {}: {}", peer, error);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+
+    Ok(reports)
+}
+
+/// Reads a connection until a deadline for the whole request, rather than one per read.
+struct Deadline {
+    stream: TcpStream,
+    at: Instant,
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the request took too long",
+            ));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    reports: &Reports,
+    agents: &HashMap<String, RemoteAgentConfig>,
+    sender: &Sender<Event>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(Deadline {
+        stream: stream.try_clone()?,
+        at: Instant::now() + REQUEST_TIMEOUT,
+    });
+
+    let mut head = reader.by_ref().take(MAX_HEADER_LEN);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    let mut token = None;
+    let mut complete = false;
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            complete = true;
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().unwrap_or(usize::MAX);
+        } else if name.eq_ignore_ascii_case("authorization") {
+            token = value.strip_prefix("Bearer ").map(str::to_string);
+        }
+    }
+
+    if !complete && head.limit() == 0 {
+        return respond(stream, "431 Request Header Fields Too Large");
+    }
+
+    let mut request = request_line.split_whitespace();
+    if (request.next(), request.next()) != (Some("POST"), Some(REPORT_PATH)) {
+        return respond(stream, "404 Not Found");
+    }
+    // The token says which agent this is, so nothing is read from anyone else.
+    let Some((name, agent)) = token.and_then(|token| {
+        agents
+            .iter()
+            .find(|(_, agent)| constant_time_eq(agent.token.as_bytes(), token.as_bytes()))
+    }) else {
+        log!(
+            "Refused a report from {} without a known token",
+            stream
+                .peer_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_default()
+        );
+        return respond(stream, "401 Unauthorized");
+    };
+    if content_length > MAX_BODY_LEN {
+        return respond(stream, "413 Payload Too Large");
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    let Ok(report) = serde_json::from_slice::<StatusReport>(&body) else {
+        return respond(stream, "400 Bad Request");
+    };
+    if report.agent != *name {
+        log!(
+            "Refused a report from agent {} claiming to be from agent {}",
+            name,
+            report.agent
+        );
+        return respond(stream, "401 Unauthorized");
+    }
+
+    receive(reports, report, agent.timeout(), sender);
+    respond(stream, "204 No Content")
+}
+
+/// Stores `report`, waking the main loop for every service whose status changed.
+fn receive(reports: &Reports, report: StatusReport, timeout: Duration, sender: &Sender<Event>) {
+    let mut agents = reports.agents.lock().unwrap();
+    let previous = agents.insert(
+        report.agent.clone(),
+        Received {
+            at: Instant::now(),
+            statuses: report.statuses,
+        },
+    );
+    let current = &agents[&report.agent].statuses;
+
+    // Every service of an agent that was lost (or never heard from) is shown again.
+    let mut changed: Vec<&String> = current.keys().collect();
+    match &previous {
+        Some(previous) if previous.at.elapsed() < timeout => {
+            changed.retain(|id| previous.statuses.get(*id) != current.get(*id));
+            changed.extend(
+                previous
+                    .statuses
+                    .keys()
+                    .filter(|id| !current.contains_key(*id)),
+            );
+        }
+        Some(previous) => {
+            log!("Agent {} is reporting again", report.agent);
+            changed.extend(previous.statuses.keys());
+        }
+        None => log!("Agent {} is reporting", report.agent),
+    }
+    for id in changed {
+        sender
+            .send(Event::ServiceChanged(format!("{}:{}", report.agent, id)))
+            .ok();
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )
+}
+
+/// Compares tokens without giving away how much of a guess was right through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
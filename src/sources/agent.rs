@@ -0,0 +1,32 @@
+use super::StatusSource;
+use crate::server::Reports;
+use crate::Status;
+use std::error::Error;
+use std::time::Duration;
+
+/// Reads the status an agent last reported for one of its services.
+pub struct AgentSource {
+    reports: Reports,
+    agent: String,
+    service: String,
+    timeout: Duration,
+}
+
+impl AgentSource {
+    pub fn new(reports: Reports, agent: String, service: String, timeout: Duration) -> AgentSource {
+        AgentSource {
+            reports,
+            agent,
+            service,
+            timeout,
+        }
+    }
+}
+
+impl StatusSource for AgentSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        self.reports
+            .status(&self.agent, &self.service, self.timeout)
+            .ok_or_else(|| format!("agent {} doesn't report {}", self.agent, self.service).into())
+    }
+}
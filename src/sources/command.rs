@@ -0,0 +1,56 @@
+use super::{wait_timeout, StatusSource};
+use crate::Status;
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Runs a shell command and maps its exit code to a status.
+pub struct CommandSource {
+    command: String,
+    exit_codes: HashMap<i32, Status>,
+    timeout: Duration,
+}
+
+impl CommandSource {
+    pub fn new(
+        command: String,
+        exit_codes: &HashMap<String, Status>,
+        timeout: Duration,
+    ) -> CommandSource {
+        CommandSource {
+            command,
+            // The config is validated, so every code parses.
+            exit_codes: exit_codes
+                .iter()
+                .filter_map(|(code, status)| Some((code.parse().ok()?, *status)))
+                .collect(),
+            timeout,
+        }
+    }
+}
+
+impl StatusSource for CommandSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        let mut child = Command::new("/bin/sh")
+            .args(["-c", &self.command])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let Some(exit_status) = wait_timeout(&mut child, self.timeout)? else {
+            return Ok(Status::Errored);
+        };
+
+        Ok(match exit_status.code() {
+            Some(code) => match self.exit_codes.get(&code) {
+                Some(status) => *status,
+                None if code == 0 => Status::Online,
+                None => Status::Failed,
+            },
+            // Killed by a signal.
+            None => Status::Errored,
+        })
+    }
+}
@@ -0,0 +1,49 @@
+use super::StatusSource;
+use crate::config::HttpCheck;
+use crate::Status;
+use reqwest::blocking::Client;
+use std::error::Error;
+use std::time::Duration;
+
+/// Requests a health endpoint, checking the status code and optionally the body.
+pub struct HttpSource {
+    check: HttpCheck,
+    client: Client,
+}
+
+impl HttpSource {
+    pub fn new(check: HttpCheck, timeout: Duration) -> reqwest::Result<HttpSource> {
+        Ok(HttpSource {
+            check,
+            client: Client::builder().timeout(timeout).build()?,
+        })
+    }
+}
+
+impl StatusSource for HttpSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        let response = match self.client.get(&self.check.url).send() {
+            Ok(response) => response,
+            Err(error) if error.is_connect() => return Ok(Status::Offline),
+            Err(error) if error.is_timeout() => return Ok(Status::Errored),
+            Err(error) => return Err(error.into()),
+        };
+
+        let status_ok = match self.check.status {
+            Some(expected) => response.status().as_u16() == expected,
+            None => response.status().is_success(),
+        };
+        if !status_ok {
+            return Ok(Status::Errored);
+        }
+
+        if let Some(expected) = &self.check.body {
+            match response.text() {
+                Ok(body) if body.contains(expected.as_str()) => {}
+                _ => return Ok(Status::Errored),
+            }
+        }
+
+        Ok(Status::Online)
+    }
+}
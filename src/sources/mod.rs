@@ -0,0 +1,112 @@
+use crate::config::{Config, ServiceConfig};
+use crate::server::Reports;
+use crate::systemd::Managers;
+use crate::Status;
+use std::error::Error;
+use std::io;
+use std::process::{Child, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod agent;
+mod command;
+mod http;
+mod process;
+mod ssh;
+mod systemd;
+mod tcp;
+
+pub use ssh::SshHosts;
+pub use systemd::PatternSource;
+
+/// Somewhere the status of a single service can be read from.
+pub trait StatusSource {
+    /// Checks the service. An error means the status can't be known at all (as opposed to the
+    /// service being down), which takes the service off the device.
+    fn status(&mut self) -> Result<Status, Box<dyn Error>>;
+}
+
+/// Builds the source for whichever check `service` configures. Local systemd units are read
+/// through the `managers`, services reported by agents from the `reports` received in server
+/// mode, and units on other hosts on their host's thread in `ssh_hosts`. Only called at
+/// startup, so a check that can't be set up stops the daemon there.
+pub fn from_config(
+    service: &ServiceConfig,
+    config: &Config,
+    managers: &Managers,
+    reports: Option<&Reports>,
+    ssh_hosts: &SshHosts,
+) -> Option<Box<dyn StatusSource>> {
+    // The config is validated, so hosts and agents exist.
+    let source: Box<dyn StatusSource> = if let Some(agent) = &service.agent {
+        Box::new(agent::AgentSource::new(
+            reports?.clone(),
+            agent.clone(),
+            service.local_id().to_string(),
+            config.server.as_ref()?.agents.get(agent)?.timeout(),
+        ))
+    } else if let (Some(unit), Some(host)) = (&service.unit, &service.host) {
+        Box::new(ssh_hosts.source(
+            service.id().to_string(),
+            host,
+            config.hosts.get(host)?,
+            unit.clone(),
+        ))
+    } else if let Some(unit) = &service.unit {
+        Box::new(systemd::SystemdSource::new(
+            service.scope.clone(),
+            managers.prober(&service.scope).cloned(),
+            unit.clone(),
+        ))
+    } else if let Some(check) = &service.http {
+        Box::new(
+            http::HttpSource::new(check.clone(), service.timeout()).unwrap_or_else(|error| {
+                eprintln!(
+                    "Could not set up an HTTP client for {}: {}",
+                    service.id(),
+                    error
+                );
+                std::process::exit(1);
+            }),
+        )
+    } else if let Some(address) = &service.tcp {
+        Box::new(tcp::TcpSource::new(address.clone(), service.timeout()))
+    } else if let Some(name) = &service.process {
+        Box::new(process::ProcessSource::Name(name.clone()))
+    } else if let Some(path) = &service.pid_file {
+        Box::new(process::ProcessSource::PidFile(path.clone()))
+    } else {
+        Box::new(command::CommandSource::new(
+            service.command.clone()?,
+            &service.exit_codes,
+            service.timeout(),
+        ))
+    };
+    Some(source)
+}
+
+/// Builds the source for a service whose `unit` is a pattern.
+pub fn pattern_from_config(service: &ServiceConfig, managers: &Managers) -> Option<PatternSource> {
+    Some(PatternSource::new(
+        service.scope.clone(),
+        managers.prober(&service.scope).cloned(),
+        service.unit.clone()?,
+    ))
+}
+
+/// Waits for `child` to exit, killing it if it takes longer than `timeout`, in which case there is
+/// no exit status.
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(exit_status) = child.try_wait()? {
+            return Ok(Some(exit_status));
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
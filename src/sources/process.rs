@@ -0,0 +1,42 @@
+use super::StatusSource;
+use crate::Status;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Checks for a running process, either by name or through its PID file.
+pub enum ProcessSource {
+    Name(String),
+    PidFile(PathBuf),
+}
+
+impl StatusSource for ProcessSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        match self {
+            ProcessSource::Name(name) => {
+                let running = fs::read_dir("/proc")?.flatten().any(|entry| {
+                    fs::read_to_string(entry.path().join("comm"))
+                        .is_ok_and(|comm| comm.trim_end() == name.as_str())
+                });
+                Ok(if running {
+                    Status::Online
+                } else {
+                    Status::Offline
+                })
+            }
+            ProcessSource::PidFile(path) => {
+                let Ok(contents) = fs::read_to_string(&*path) else {
+                    // Daemons remove their PID file when they shut down cleanly.
+                    return Ok(Status::Offline);
+                };
+                let pid: u32 = contents.trim().parse()?;
+                // A PID file left behind by a process that is gone means it didn't exit cleanly.
+                Ok(if Path::new(&format!("/proc/{}", pid)).exists() {
+                    Status::Online
+                } else {
+                    Status::Failed
+                })
+            }
+        }
+    }
+}
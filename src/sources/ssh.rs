@@ -0,0 +1,253 @@
+use super::systemd::unit_state_to_status;
+use super::{wait_timeout, StatusSource};
+use crate::config::HostConfig;
+use crate::systemd::UnitState;
+use crate::{Event, Status};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Instant;
+
+/// The exit code of `ssh` itself failing, as opposed to the remote command.
+const SSH_ERROR: i32 = 255;
+/// How long an idle shared connection stays open after the last check, in seconds.
+const CONTROL_PERSIST: u64 = 300;
+
+/// Reads the state of a systemd unit on another machine by running `systemctl` over SSH.
+///
+/// Checks run on a thread of their host's own, so a host that is slow to answer doesn't hold up
+/// the other services. A poll starts a check and reports the result of the last one, or `unknown`
+/// until there is one, and a check that changes the result has the service polled again right
+/// away. Checks share one connection per host (OpenSSH's `ControlMaster`), so only the first check
+/// pays for the handshake.
+pub struct SshSource {
+    id: String,
+    unit: String,
+    checks: Sender<Check>,
+    checked: Arc<Mutex<Checked>>,
+}
+
+/// The outcome of a unit's checks, shared with its host's thread.
+#[derive(Default)]
+struct Checked {
+    result: Option<Result<Status, String>>,
+    /// Set from the time a check is queued until it is done.
+    checking: bool,
+    /// Set when the last check changed the result, until a poll reports it.
+    changed: bool,
+}
+
+/// A check for a host's thread to run.
+struct Check {
+    id: String,
+    unit: String,
+    queued: Instant,
+    checked: Arc<Mutex<Checked>>,
+}
+
+/// The thread checking each host's units, by host name, started for the first unit on a host.
+pub struct SshHosts {
+    events: Sender<Event>,
+    threads: RefCell<HashMap<String, Sender<Check>>>,
+}
+
+impl SshHosts {
+    /// Hosts whose checks changed a result send `events`.
+    pub fn new(events: Sender<Event>) -> SshHosts {
+        SshHosts {
+            events,
+            threads: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The source for `unit` on the host called `name`, which is reported as `id`.
+    pub fn source(&self, id: String, name: &str, host: &HostConfig, unit: String) -> SshSource {
+        let checks = self
+            .threads
+            .borrow_mut()
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let (checks, receiver) = mpsc::channel();
+                let host = host.clone();
+                let events = self.events.clone();
+                thread::spawn(move || run_checks(&host, receiver, &events));
+                checks
+            })
+            .clone();
+        SshSource {
+            id,
+            unit,
+            checks,
+            checked: Arc::default(),
+        }
+    }
+}
+
+impl StatusSource for SshSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        let mut checked = self.checked.lock().unwrap();
+        // The poll reporting a changed result was just checked, so the next check waits for the
+        // one after.
+        if !std::mem::take(&mut checked.changed) && !checked.checking {
+            checked.checking = self
+                .checks
+                .send(Check {
+                    id: self.id.clone(),
+                    unit: self.unit.clone(),
+                    queued: Instant::now(),
+                    checked: self.checked.clone(),
+                })
+                .is_ok();
+        }
+        match &checked.result {
+            Some(result) => result.clone().map_err(Into::into),
+            None => Ok(Status::Unknown),
+        }
+    }
+}
+
+/// Runs the checks queued for `host`, one at a time, until the daemon exits.
+fn run_checks(host: &HostConfig, checks: Receiver<Check>, events: &Sender<Event>) {
+    // Checks queued before the host was last found unreachable, such as those of the same poll,
+    // don't wait for it to time out again.
+    let mut unreachable_at: Option<Instant> = None;
+    for check in checks {
+        let result = if unreachable_at.is_some_and(|at| check.queued <= at) {
+            Ok(Status::Unreachable)
+        } else {
+            let result = check_unit(host, &check.unit).map_err(|error| error.to_string());
+            if result == Ok(Status::Unreachable) {
+                unreachable_at = Some(Instant::now());
+            }
+            result
+        };
+
+        let mut checked = check.checked.lock().unwrap();
+        checked.checking = false;
+        if checked.result.as_ref() != Some(&result) {
+            checked.result = Some(result);
+            checked.changed = true;
+            drop(checked);
+            events.send(Event::ServiceChanged(check.id)).ok();
+        }
+    }
+}
+
+fn command(host: &HostConfig, unit: &str) -> Command {
+    let timeout = host.timeout().as_secs();
+    let mut command = Command::new("ssh");
+    command
+        // Never prompt for a password or host key, there is nobody to answer.
+        .args(["-o", "BatchMode=yes"])
+        .args(["-o", &format!("ConnectTimeout={}", timeout)])
+        .args(["-o", "ControlMaster=auto"])
+        .args(["-o", &format!("ControlPersist={}", CONTROL_PERSIST)]);
+    if let Some(dir) = control_dir() {
+        command.args(["-o", &format!("ControlPath={}", dir.join("%C").display())]);
+    }
+    if let Some(identity_file) = &host.identity_file {
+        command
+            .arg("-i")
+            .arg(identity_file)
+            .args(["-o", "IdentitiesOnly=yes"]);
+    }
+    if let Some(port) = host.port {
+        command.args(["-p", &port.to_string()]);
+    }
+    if let Some(user) = &host.user {
+        command.args(["-l", user]);
+    }
+    command.arg("--").arg(&host.address).arg(format!(
+        "systemctl show --property=LoadState,ActiveState,SubState,Result -- {}",
+        shell_quote(unit)
+    ));
+    command
+}
+
+fn check_unit(host: &HostConfig, unit: &str) -> Result<Status, Box<dyn Error>> {
+    let mut child = command(host, unit)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    let Some(exit_status) = wait_timeout(&mut child, host.timeout())? else {
+        return Ok(Status::Unreachable);
+    };
+    match exit_status.code() {
+        Some(0) => {}
+        Some(SSH_ERROR) => return Ok(Status::Unreachable),
+        Some(code) => return Err(format!("systemctl exited with {}", code).into()),
+        None => return Err("ssh was killed".into()),
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+    let properties: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    if properties.get("LoadState") == Some(&"not-found") {
+        return Err(format!("Unit {} not found", unit).into());
+    }
+
+    let property = |name: &str| {
+        properties
+            .get(name)
+            .map(|value| value.to_string())
+            .ok_or_else(|| format!("systemctl didn't report {}", name))
+    };
+    Ok(unit_state_to_status(&UnitState {
+        active_state: property("ActiveState")?,
+        sub_state: property("SubState")?,
+        // Only some unit types have a result.
+        result: property("Result").ok().filter(|result| !result.is_empty()),
+    }))
+}
+
+/// Where the shared connections' sockets live: in the user's runtime directory, or else the
+/// temporary directory, where anyone could have made the directory first. Either way it is only
+/// used if it belongs to this user and nobody else can get into it, otherwise connections aren't
+/// shared.
+fn control_dir() -> Option<&'static PathBuf> {
+    static CONTROL_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    CONTROL_DIR
+        .get_or_init(|| {
+            // /proc/self belongs to the effective user.
+            let uid = std::fs::metadata("/proc/self").ok()?.uid();
+            let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+                Some(runtime_dir) => PathBuf::from(runtime_dir).join("app_status_ssh"),
+                None => std::env::temp_dir().join(format!("app_status_ssh_{}", uid)),
+            };
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .ok()?;
+            // Not following symlinks, which could point anywhere.
+            let metadata = std::fs::symlink_metadata(&dir).ok()?;
+            if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+                log!(
+                    "Not sharing SSH connections, as {} is not private to this user",
+                    dir.display()
+                );
+                return None;
+            }
+            Some(dir)
+        })
+        .as_ref()
+}
+
+/// Quotes `value` for the remote shell, which is what actually runs the command.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
@@ -0,0 +1,147 @@
+use super::StatusSource;
+use crate::config::Scope;
+use crate::systemd::{glob_match, UnitProber, UnitState};
+use crate::Status;
+use std::error::Error;
+
+pub struct SystemdSource {
+    manager: Manager,
+    unit: String,
+}
+
+impl SystemdSource {
+    pub fn new(scope: Scope, prober: Option<UnitProber>, unit: String) -> SystemdSource {
+        SystemdSource {
+            manager: Manager { scope, prober },
+            unit,
+        }
+    }
+}
+
+impl StatusSource for SystemdSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        let state = self.manager.call(|prober| prober.unit_state(&self.unit))?;
+        Ok(unit_state_to_status(&state))
+    }
+}
+
+/// Reads the state of every loaded unit matching a glob pattern, e.g. `worker@*.service`, so
+/// units that come and go, such as template instances, are found on every poll.
+pub struct PatternSource {
+    manager: Manager,
+    pattern: String,
+}
+
+impl PatternSource {
+    pub fn new(scope: Scope, prober: Option<UnitProber>, pattern: String) -> PatternSource {
+        PatternSource {
+            manager: Manager { scope, prober },
+            pattern,
+        }
+    }
+
+    /// The status of every unit that matches, by unit name.
+    pub fn statuses(&mut self) -> Result<Vec<(String, Status)>, Box<dyn Error>> {
+        let pattern = &self.pattern;
+        let mut units = self
+            .manager
+            .call(|prober| prober.list_units(|unit| glob_match(pattern, unit)))?;
+        units.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(units
+            .into_iter()
+            .map(|(unit, state)| (unit, unit_state_to_status(&state)))
+            .collect())
+    }
+}
+
+/// The connection to a unit's manager, made on the next check when there is none yet.
+struct Manager {
+    scope: Scope,
+    prober: Option<UnitProber>,
+}
+
+impl Manager {
+    fn call<T>(
+        &mut self,
+        f: impl FnOnce(&UnitProber) -> zbus::Result<T>,
+    ) -> Result<T, Box<dyn Error>> {
+        let prober = match &self.prober {
+            Some(prober) => prober,
+            None => self.prober.insert(UnitProber::connect(&self.scope)?),
+        };
+        f(prober).map_err(|error| {
+            // A user manager that went away with its session, or a container that stopped,
+            // needs a new connection once it is back. The subscription made on the old one ended
+            // with it, so the manager's units are polled as often as unsubscribed ones from now on.
+            if self.scope != Scope::System && matches!(error, zbus::Error::InputOutput(_)) {
+                self.prober = None;
+            }
+            error.into()
+        })
+    }
+}
+
+/// Maps a unit's state to a status, wherever the state was read from.
+pub fn unit_state_to_status(state: &UnitState) -> Status {
+    match (state.active_state.as_str(), state.sub_state.as_str()) {
+        ("active", _) => Status::Online,
+        // A unit that stopped on its own (e.g. killed by a signal or the watchdog) ends up inactive
+        // rather than failed if it is allowed to, but it still didn't stop cleanly.
+        ("inactive", _) => match state.result.as_deref() {
+            Some("success") | None => Status::Offline,
+            Some(_) => Status::Errored,
+        },
+        ("failed", _) => Status::Failed,
+        // Crash-looping services sit in "activating (auto-restart)" between attempts.
+        ("activating", "auto-restart") => Status::Failed,
+        ("activating", _) => Status::Activating,
+        ("deactivating", _) => Status::Deactivating,
+        ("reloading", _) | ("refreshing", _) => Status::Reloading,
+        ("maintenance", _) => Status::Maintenance,
+        _ => Status::Errored,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_unit_states() {
+        let cases = [
+            ("active", "running", None, Status::Online),
+            ("active", "exited", Some("success"), Status::Online),
+            ("inactive", "dead", None, Status::Offline),
+            ("inactive", "dead", Some("success"), Status::Offline),
+            ("inactive", "dead", Some("signal"), Status::Errored),
+            ("failed", "failed", Some("exit-code"), Status::Failed),
+            (
+                "activating",
+                "auto-restart",
+                Some("exit-code"),
+                Status::Failed,
+            ),
+            ("activating", "start-pre", None, Status::Activating),
+            ("deactivating", "stop-sigterm", None, Status::Deactivating),
+            ("reloading", "reload", None, Status::Reloading),
+            ("refreshing", "refresh", None, Status::Reloading),
+            ("maintenance", "cleaning", None, Status::Maintenance),
+            ("unknown", "", None, Status::Errored),
+        ];
+        for (active_state, sub_state, result, expected) in cases {
+            let state = UnitState {
+                active_state: active_state.to_string(),
+                sub_state: sub_state.to_string(),
+                result: result.map(str::to_string),
+            };
+            assert_eq!(
+                unit_state_to_status(&state),
+                expected,
+                "{} ({}), result {:?}",
+                active_state,
+                sub_state,
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use super::StatusSource;
+use crate::Status;
+use std::error::Error;
+use std::io::{self, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Checks that a port accepts connections.
+pub struct TcpSource {
+    address: String,
+    timeout: Duration,
+}
+
+impl TcpSource {
+    pub fn new(address: String, timeout: Duration) -> TcpSource {
+        TcpSource { address, timeout }
+    }
+}
+
+impl StatusSource for TcpSource {
+    fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        // Resolved on every check, in case the name points somewhere else by now.
+        let addresses: Vec<_> = self.address.to_socket_addrs()?.collect();
+        let mut last_error = io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} did not resolve to any address", self.address),
+        );
+
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(_) => return Ok(Status::Online),
+                Err(error) => last_error = error,
+            }
+        }
+
+        match last_error.kind() {
+            ErrorKind::ConnectionRefused => Ok(Status::Offline),
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Ok(Status::Errored),
+            ErrorKind::NotFound => Err(last_error.into()),
+            _ => Ok(Status::Errored),
+        }
+    }
+}
@@ -0,0 +1,648 @@
+use crate::config::Scope;
+use crate::Event;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use zbus::blocking::proxy::Builder;
+use zbus::blocking::{connection, Connection, MessageIterator, Proxy};
+use zbus::message::Type;
+use zbus::proxy::CacheProperties;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::MatchRule;
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const UNIT_PATH_NAMESPACE: &str = "/org/freedesktop/systemd1/unit";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const MACHINED_DESTINATION: &str = "org.freedesktop.machine1";
+const MACHINED_PATH: &str = "/org/freedesktop/machine1";
+const MACHINED_MANAGER_INTERFACE: &str = "org.freedesktop.machine1.Manager";
+const MACHINE_INTERFACE: &str = "org.freedesktop.machine1.Machine";
+
+/// A unit as `ListUnits` describes it: name, description, load, active and sub state, followed
+/// unit, object path, and the queued job's ID, type and path.
+type ListedUnit = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    String,
+    OwnedObjectPath,
+    u32,
+    String,
+    OwnedObjectPath,
+);
+
+/// The raw state of a unit as reported by systemd.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitState {
+    pub active_state: String,
+    pub sub_state: String,
+    /// `Result` lives on the type-specific interface (e.g. `org.freedesktop.systemd1.Service`),
+    /// so unit types without one (targets, slices, ...) leave this empty.
+    pub result: Option<String>,
+}
+
+/// The systemd managers that services' units are in, by scope.
+#[derive(Default)]
+pub struct Managers {
+    probers: HashMap<Scope, UnitProber>,
+    subscriptions: HashMap<Scope, Subscription>,
+}
+
+impl Managers {
+    /// Connects to the manager of every scope in `units`, which lists the `(unit, id)` pairs in
+    /// each, and subscribes to changes of the units. The system manager has to be there, so not
+    /// reaching it is an error, but user managers and containers come and go, so their units are
+    /// only read once they are up.
+    pub fn connect(
+        units: &HashMap<Scope, Vec<(String, String)>>,
+        sender: Sender<Event>,
+    ) -> zbus::Result<Managers> {
+        let mut managers = Managers::default();
+        for (scope, units) in units {
+            let prober = match UnitProber::connect(scope) {
+                Ok(prober) => prober,
+                Err(error) if *scope == Scope::System => return Err(error),
+                Err(error) => {
+                    log!("Could not connect to the {} manager yet: {}", scope, error);
+                    continue;
+                }
+            };
+            match prober.subscribe(units, sender.clone()) {
+                Ok(subscription) => {
+                    managers.subscriptions.insert(scope.clone(), subscription);
+                }
+                Err(error) => log!(
+                    "Could not subscribe to unit changes of the {} manager, polling instead: {}",
+                    scope,
+                    error
+                ),
+            }
+            managers.probers.insert(scope.clone(), prober);
+        }
+        Ok(managers)
+    }
+
+    /// The connection to the manager of `scope`, if there is one.
+    pub fn prober(&self, scope: &Scope) -> Option<&UnitProber> {
+        self.probers.get(scope)
+    }
+
+    /// The subscription to changes of units in `scope`, if there is one.
+    pub fn subscription(&self, scope: &Scope) -> Option<Subscription> {
+        self.subscriptions.get(scope).cloned()
+    }
+}
+
+/// Tells whether a manager's unit changes are still coming in, so polling can be rare. Clones
+/// share the same state.
+#[derive(Clone)]
+pub struct Subscription {
+    active: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// False once the connection the changes came in on is gone, e.g. because the manager went
+    /// away with its session or container.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+}
+
+/// Reads unit state straight from the systemd manager over D-Bus, so no `systemctl` process is
+/// spawned per poll. Clones share the same connection.
+#[derive(Clone)]
+pub struct UnitProber {
+    connection: Connection,
+}
+
+impl UnitProber {
+    /// Connects to the manager of `scope`: the system manager on the system bus, a user manager
+    /// on that user's bus, or a container's system manager on the bus inside the container, the
+    /// way `systemctl --user` and `systemctl --machine` reach them.
+    pub fn connect(scope: &Scope) -> zbus::Result<UnitProber> {
+        let address = match scope {
+            Scope::System => return Ok(UnitProber::new(Connection::system()?)),
+            Scope::User(uid) => format!("unix:path=/run/user/{}/bus", uid),
+            // The container's root is reachable through its leader process.
+            Scope::Machine(name) => format!(
+                "unix:path=/proc/{}/root/run/dbus/system_bus_socket",
+                machine_leader(name)?
+            ),
+        };
+        Ok(UnitProber::new(
+            connection::Builder::address(address.as_str())?.build()?,
+        ))
+    }
+
+    /// Uses an existing connection, e.g. one built with
+    /// `zbus::blocking::connection::Builder::address` to talk to a mock bus.
+    pub fn new(connection: Connection) -> UnitProber {
+        UnitProber { connection }
+    }
+
+    pub fn unit_state(&self, unit: &str) -> zbus::Result<UnitState> {
+        let unit = &full_unit_name(unit);
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        // LoadUnit (unlike GetUnit) also works for units that aren't currently loaded, e.g. a
+        // service that has been stopped for a while.
+        let path: OwnedObjectPath = manager.call("LoadUnit", &(unit,))?;
+
+        let unit_proxy = self.proxy(path.as_str(), UNIT_INTERFACE)?;
+        let load_state: String = unit_proxy.get_property("LoadState")?;
+        if load_state == "not-found" {
+            return Err(zbus::Error::Failure(format!("Unit {} not found", unit)));
+        }
+
+        Ok(UnitState {
+            active_state: unit_proxy.get_property("ActiveState")?,
+            sub_state: unit_proxy.get_property("SubState")?,
+            result: self.result(unit, path.as_str()),
+        })
+    }
+
+    /// The name and state of every unit the manager has loaded, which includes every running one,
+    /// whose names match `filter`. The states come with the list, except for `Result`, which is
+    /// only read for inactive units, the one state it says anything about.
+    pub fn list_units(
+        &self,
+        filter: impl Fn(&str) -> bool,
+    ) -> zbus::Result<Vec<(String, UnitState)>> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        let units: Vec<ListedUnit> = manager.call("ListUnits", &())?;
+        Ok(units
+            .into_iter()
+            .filter(|unit| filter(&unit.0))
+            .map(|(name, _, _, active_state, sub_state, _, path, ..)| {
+                let result = match active_state.as_str() {
+                    "inactive" => self.result(&name, path.as_str()),
+                    _ => None,
+                };
+                let state = UnitState {
+                    active_state,
+                    sub_state,
+                    result,
+                };
+                (name, state)
+            })
+            .collect())
+    }
+
+    /// Sends the service id given with a unit, as `(unit, id)`, down `sender` whenever the unit's
+    /// `ActiveState` or `SubState` changes, from a background thread that lives until the
+    /// receiving end is dropped or the connection closes. If the connection closes, every id is
+    /// sent once more so the units are polled right away.
+    pub fn subscribe(
+        &self,
+        units: &[(String, String)],
+        sender: Sender<Event>,
+    ) -> zbus::Result<Subscription> {
+        let manager = self.proxy(MANAGER_PATH, MANAGER_INTERFACE)?;
+        // systemd only emits unit signals while at least one client is subscribed.
+        manager.call_method("Subscribe", &())?;
+
+        let mut unit_paths: HashMap<OwnedObjectPath, String> = HashMap::new();
+        for (unit, id) in units {
+            let path: OwnedObjectPath = manager.call("LoadUnit", &(full_unit_name(unit),))?;
+            unit_paths.insert(path, id.clone());
+        }
+
+        let rule = MatchRule::builder()
+            .msg_type(Type::Signal)
+            .sender(SYSTEMD_DESTINATION)?
+            .interface(PROPERTIES_INTERFACE)?
+            .member("PropertiesChanged")?
+            .path_namespace(UNIT_PATH_NAMESPACE)?
+            .arg(0, UNIT_INTERFACE)?
+            .build();
+        let messages = MessageIterator::for_match_rule(rule, &self.connection, None)?;
+
+        let subscription = Subscription {
+            active: Arc::new(AtomicBool::new(true)),
+        };
+        let active = subscription.active.clone();
+        thread::spawn(move || {
+            for message in messages.flatten() {
+                let header = message.header();
+                let Some(unit) = header
+                    .path()
+                    .and_then(|path| unit_paths.get(&OwnedObjectPath::from(path.to_owned())))
+                else {
+                    continue;
+                };
+
+                let Ok((_, changed, _)) =
+                    message
+                        .body()
+                        .deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>()
+                else {
+                    continue;
+                };
+
+                if (changed.contains_key("ActiveState") || changed.contains_key("SubState"))
+                    && sender.send(Event::ServiceChanged(unit.clone())).is_err()
+                {
+                    return;
+                }
+            }
+
+            active.store(false, Ordering::Relaxed);
+            log!(
+                "Lost the subscription to changes of {}, polling instead",
+                unit_paths
+                    .values()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            for id in unit_paths.into_values() {
+                sender.send(Event::ServiceChanged(id)).ok();
+            }
+        });
+
+        Ok(subscription)
+    }
+
+    /// The `Result` of the unit at `path`, for unit types that have one.
+    fn result(&self, unit: &str, path: &str) -> Option<String> {
+        let interface = unit_type_interface(unit)?;
+        let proxy = self.proxy(path, &interface).ok()?;
+        proxy.get_property("Result").ok()
+    }
+
+    fn proxy<'a>(&self, path: &'a str, interface: &'a str) -> zbus::Result<Proxy<'a>> {
+        Builder::new(&self.connection)
+            .destination(SYSTEMD_DESTINATION)?
+            .path(path)?
+            .interface(interface)?
+            .cache_properties(CacheProperties::No)
+            .build()
+    }
+}
+
+/// The PID of the first process in a container registered with systemd-machined.
+fn machine_leader(name: &str) -> zbus::Result<u32> {
+    let connection = Connection::system()?;
+    let manager: Proxy = Builder::new(&connection)
+        .destination(MACHINED_DESTINATION)?
+        .path(MACHINED_PATH)?
+        .interface(MACHINED_MANAGER_INTERFACE)?
+        .build()?;
+    let path: OwnedObjectPath = manager.call("GetMachine", &(name,))?;
+    let machine: Proxy = Builder::new(&connection)
+        .destination(MACHINED_DESTINATION)?
+        .path(path.as_str())?
+        .interface(MACHINE_INTERFACE)?
+        .cache_properties(CacheProperties::No)
+        .build()?;
+    machine.get_property("Leader")
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of characters, `?` any
+/// one character and `[...]` any of a set (or range, e.g. `[0-9]`) of characters, like `systemctl`
+/// patterns. The pattern gets the same default unit type as unit names.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = full_unit_name(pattern).chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_from(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let Some(end) = pattern
+                .iter()
+                .skip(2)
+                .position(|c| *c == ']')
+                .map(|i| i + 2)
+            else {
+                // An unclosed bracket is just a bracket.
+                return name.first() == Some(&'[') && matches_from(&pattern[1..], &name[1..]);
+            };
+            let Some(c) = name.first() else {
+                return false;
+            };
+            let (negated, set) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..end]),
+                _ => (false, &pattern[1..end]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= (set[i]..=set[i + 2]).contains(c);
+                    i += 3;
+                } else {
+                    found |= set[i] == *c;
+                    i += 1;
+                }
+            }
+            found != negated && matches_from(&pattern[end + 1..], &name[1..])
+        }
+        Some(p) => name.first() == Some(p) && matches_from(&pattern[1..], &name[1..]),
+    }
+}
+
+/// `systemctl` assumes `.service` when no unit type is given; the D-Bus API doesn't.
+pub fn full_unit_name(unit: &str) -> String {
+    if unit.contains('.') {
+        unit.to_string()
+    } else {
+        format!("{}.service", unit)
+    }
+}
+
+/// Maps `nginx.service` to `org.freedesktop.systemd1.Service`.
+fn unit_type_interface(unit: &str) -> Option<String> {
+    let (_, unit_type) = unit.rsplit_once('.')?;
+    let mut chars = unit_type.chars();
+    let first = chars.next()?.to_ascii_uppercase();
+    Some(format!(
+        "org.freedesktop.systemd1.{}{}",
+        first,
+        chars.as_str()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use zbus::Guid;
+
+    const NGINX_PATH: &str = "/org/freedesktop/systemd1/unit/nginx_2eservice";
+    const TARGET_PATH: &str = "/org/freedesktop/systemd1/unit/multi_2duser_2etarget";
+    const MISSING_PATH: &str = "/org/freedesktop/systemd1/unit/missing_2eservice";
+    const WORKER1_PATH: &str = "/org/freedesktop/systemd1/unit/worker_401_2eservice";
+    const WORKER2_PATH: &str = "/org/freedesktop/systemd1/unit/worker_402_2eservice";
+
+    /// Answers `LoadUnit` the way systemd does: with a path even for units that don't exist.
+    struct MockManager;
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Manager")]
+    impl MockManager {
+        fn subscribe(&self) {}
+
+        fn load_unit(&self, name: &str) -> OwnedObjectPath {
+            let path = match name {
+                "nginx.service" => NGINX_PATH,
+                "multi-user.target" => TARGET_PATH,
+                _ => MISSING_PATH,
+            };
+            OwnedObjectPath::try_from(path).unwrap()
+        }
+
+        fn list_units(&self) -> Vec<ListedUnit> {
+            [
+                ("nginx.service", "failed", "failed", NGINX_PATH),
+                ("multi-user.target", "active", "active", TARGET_PATH),
+                ("worker@1.service", "active", "running", WORKER1_PATH),
+                ("worker@2.service", "inactive", "dead", WORKER2_PATH),
+            ]
+            .into_iter()
+            .map(|(name, active_state, sub_state, path)| {
+                (
+                    name.to_string(),
+                    String::new(),
+                    "loaded".to_string(),
+                    active_state.to_string(),
+                    sub_state.to_string(),
+                    String::new(),
+                    OwnedObjectPath::try_from(path).unwrap(),
+                    0,
+                    String::new(),
+                    OwnedObjectPath::try_from("/").unwrap(),
+                )
+            })
+            .collect()
+        }
+    }
+
+    struct MockUnit {
+        load_state: &'static str,
+        active_state: &'static str,
+        sub_state: &'static str,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Unit")]
+    impl MockUnit {
+        #[zbus(property)]
+        fn load_state(&self) -> String {
+            self.load_state.to_string()
+        }
+
+        #[zbus(property)]
+        fn active_state(&self) -> String {
+            self.active_state.to_string()
+        }
+
+        #[zbus(property)]
+        fn sub_state(&self) -> String {
+            self.sub_state.to_string()
+        }
+    }
+
+    struct MockService;
+
+    #[zbus::interface(name = "org.freedesktop.systemd1.Service")]
+    impl MockService {
+        #[zbus(property)]
+        fn result(&self) -> String {
+            "exit-code".to_string()
+        }
+    }
+
+    /// A prober talking to a mock manager over a private connection, and the mock's end of it,
+    /// which has to be kept alive for as long as the prober is used.
+    fn mock_prober() -> (UnitProber, Connection) {
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            connection::Builder::async_io_unix_stream(server)
+                .server(Guid::generate())
+                .unwrap()
+                .p2p()
+                .serve_at(MANAGER_PATH, MockManager)
+                .unwrap()
+                .serve_at(
+                    NGINX_PATH,
+                    MockUnit {
+                        load_state: "loaded",
+                        active_state: "failed",
+                        sub_state: "failed",
+                    },
+                )
+                .unwrap()
+                .serve_at(NGINX_PATH, MockService)
+                .unwrap()
+                .serve_at(WORKER2_PATH, MockService)
+                .unwrap()
+                .serve_at(
+                    TARGET_PATH,
+                    MockUnit {
+                        load_state: "loaded",
+                        active_state: "active",
+                        sub_state: "active",
+                    },
+                )
+                .unwrap()
+                .serve_at(
+                    MISSING_PATH,
+                    MockUnit {
+                        load_state: "not-found",
+                        active_state: "inactive",
+                        sub_state: "dead",
+                    },
+                )
+                .unwrap()
+                .build()
+                .unwrap()
+        });
+        let client = connection::Builder::async_io_unix_stream(client)
+            .p2p()
+            .build()
+            .unwrap();
+        (UnitProber::new(client), server.join().unwrap())
+    }
+
+    #[test]
+    fn reads_unit_state() {
+        let (prober, _server) = mock_prober();
+        assert_eq!(
+            prober.unit_state("nginx").unwrap(),
+            UnitState {
+                active_state: "failed".to_string(),
+                sub_state: "failed".to_string(),
+                result: Some("exit-code".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn unit_without_result() {
+        let (prober, _server) = mock_prober();
+        assert_eq!(
+            prober.unit_state("multi-user.target").unwrap(),
+            UnitState {
+                active_state: "active".to_string(),
+                sub_state: "active".to_string(),
+                result: None,
+            }
+        );
+    }
+
+    #[test]
+    fn unit_not_found() {
+        let (prober, _server) = mock_prober();
+        let error = prober.unit_state("missing").unwrap_err();
+        assert!(
+            error.to_string().contains("Unit missing.service not found"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn subscription_ends_with_connection() {
+        let (prober, server) = mock_prober();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let subscription = prober
+            .subscribe(&[("nginx".to_string(), "web".to_string())], sender)
+            .unwrap();
+        assert!(subscription.is_active());
+
+        server.close().unwrap();
+        let event = receiver
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        assert!(matches!(event, Event::ServiceChanged(id) if id == "web"));
+        assert!(!subscription.is_active());
+    }
+
+    #[test]
+    fn lists_matching_units_with_their_state() {
+        let (prober, _server) = mock_prober();
+        let units = prober
+            .list_units(|unit| glob_match("worker@*", unit))
+            .unwrap();
+        assert_eq!(
+            units,
+            [
+                (
+                    "worker@1.service".to_string(),
+                    UnitState {
+                        active_state: "active".to_string(),
+                        sub_state: "running".to_string(),
+                        result: None,
+                    }
+                ),
+                (
+                    "worker@2.service".to_string(),
+                    UnitState {
+                        active_state: "inactive".to_string(),
+                        sub_state: "dead".to_string(),
+                        result: Some("exit-code".to_string()),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn glob_stars_match_any_run() {
+        assert!(glob_match("worker@*.service", "worker@1.service"));
+        assert!(glob_match("worker@*.service", "worker@.service"));
+        assert!(glob_match("app-*.timer", "app-backup-daily.timer"));
+        assert!(!glob_match("app-*.timer", "app-backup.service"));
+        assert!(glob_match("*", "anything.service"));
+    }
+
+    #[test]
+    fn glob_question_marks_match_one_character() {
+        assert!(glob_match("worker@?.service", "worker@1.service"));
+        assert!(!glob_match("worker@?.service", "worker@10.service"));
+        assert!(!glob_match("worker@?.service", "worker@.service"));
+    }
+
+    #[test]
+    fn glob_brackets_match_sets_and_ranges() {
+        assert!(glob_match("worker@[ab].service", "worker@b.service"));
+        assert!(!glob_match("worker@[ab].service", "worker@c.service"));
+        assert!(glob_match("worker@[0-9].service", "worker@7.service"));
+        assert!(!glob_match("worker@[0-9].service", "worker@x.service"));
+        assert!(glob_match("worker@[a-c0-9].service", "worker@5.service"));
+        // A `]` right after the opening bracket is part of the set.
+        assert!(glob_match("x[]].service", "x].service"));
+    }
+
+    #[test]
+    fn glob_brackets_can_be_negated() {
+        assert!(glob_match("worker@[!0-9].service", "worker@x.service"));
+        assert!(!glob_match("worker@[!0-9].service", "worker@1.service"));
+        assert!(glob_match("worker@[^0-9].service", "worker@x.service"));
+        assert!(!glob_match("worker@[^0-9].service", "worker@1.service"));
+    }
+
+    #[test]
+    fn glob_patterns_default_to_services() {
+        assert!(glob_match("nginx", "nginx.service"));
+        assert!(!glob_match("nginx", "nginx.socket"));
+        assert!(glob_match("worker@*", "worker@1.service"));
+        assert!(!glob_match("worker@*", "worker@1.socket"));
+    }
+
+    #[test]
+    fn glob_unclosed_bracket_is_literal() {
+        assert!(glob_match("odd[.service", "odd[.service"));
+        assert!(!glob_match("odd[.service", "oddx.service"));
+        assert!(!glob_match("odd[", "odd.service"));
+    }
+}
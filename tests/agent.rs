@@ -0,0 +1,203 @@
+//! Runs the daemon as a server driving the mock Particle Cloud, with an agent reporting to it.
+
+mod common;
+
+use common::{free_address, test_dir, write_config, Daemon, MockCloud, MockOptions};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+const AGENT_SERVICES: &str = r#"
+[[services]]
+name = "healthy"
+command = "true"
+
+[[services]]
+name = "broken"
+command = "false"
+"#;
+
+/// The server's config, accepting agent `web1` and showing its two services.
+fn server_config(listen: &str) -> String {
+    format!(
+        r#"
+[server]
+listen = "{}"
+
+[server.agents.web1]
+token = "secret"
+timeout = 2
+
+[[services]]
+agent = "web1"
+name = "healthy"
+led = 1
+
+[[services]]
+agent = "web1"
+name = "broken"
+led = 2
+"#,
+        listen
+    )
+}
+
+/// Waits for a call that sets `pair`, e.g. `1:online`, returning whether one came.
+fn wait_for(cloud: &MockCloud, pair: &str) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(15);
+    while Instant::now() < deadline {
+        match cloud.next_call() {
+            Some(call) if call.pairs().iter().any(|shown| shown == pair) => return true,
+            Some(_) => {}
+            None => return false,
+        }
+    }
+    false
+}
+
+/// POSTs `statuses` to the server as agent `web1`, returning the response.
+fn report(listen: &str, statuses: &str) -> String {
+    let body = format!(r#"{{"agent":"web1","statuses":{}}}"#, statuses);
+    let mut stream = TcpStream::connect(listen).unwrap();
+    write!(
+        stream,
+        "POST /v1/report HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn agent_reports_are_shown_until_the_agent_is_lost() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let listen = free_address();
+    let server_dir = test_dir("agent_server");
+    let _server = Daemon::start_as(
+        "server",
+        &write_config(&server_dir, &cloud, &server_config(&listen)),
+    );
+
+    let agent_dir = test_dir("agent_agent");
+    let agent_config = format!(
+        "[agent]\nname = \"web1\"\nserver = \"http://{}\"\ntoken = \"secret\"\nheartbeat = 1\n{}",
+        listen, AGENT_SERVICES
+    );
+    let config_path = agent_dir.join("config.toml");
+    std::fs::write(&config_path, agent_config).unwrap();
+    let agent = Daemon::start_as("agent", &config_path);
+
+    assert!(
+        wait_for(&cloud, "1:online"),
+        "the agent's report wasn't shown"
+    );
+
+    drop(agent);
+    assert!(
+        wait_for(&cloud, "1:lost+flash"),
+        "the agent wasn't shown as lost"
+    );
+}
+
+#[test]
+fn reports_with_the_wrong_token_are_refused() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let listen = free_address();
+    let dir = test_dir("agent_token");
+    let _server = Daemon::start_as(
+        "server",
+        &write_config(&dir, &cloud, &server_config(&listen)),
+    );
+    cloud.next_call().expect("the server didn't start");
+
+    let body = r#"{"agent":"web1","statuses":{"healthy":"online"}}"#;
+    let mut stream = TcpStream::connect(&listen).unwrap();
+    write!(
+        stream,
+        "POST /v1/report HTTP/1.1\r\nAuthorization: Bearer guess\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+}
+
+#[test]
+fn the_token_is_checked_before_the_body_is_read() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let listen = free_address();
+    let dir = test_dir("agent_token_first");
+    let _server = Daemon::start_as(
+        "server",
+        &write_config(&dir, &cloud, &server_config(&listen)),
+    );
+    cloud.next_call().expect("the server didn't start");
+
+    // The body never comes, so only an early answer gets through before the client gives up.
+    let mut stream = TcpStream::connect(&listen).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "POST /v1/report HTTP/1.1\r\nAuthorization: Bearer guess\r\nContent-Length: 1000\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 401"), "{}", response);
+}
+
+#[test]
+fn headers_are_only_read_up_to_a_limit() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let listen = free_address();
+    let dir = test_dir("agent_header_limit");
+    let _server = Daemon::start_as(
+        "server",
+        &write_config(&dir, &cloud, &server_config(&listen)),
+    );
+    cloud.next_call().expect("the server didn't start");
+
+    // Exactly as much as the server reads (8 KiB), with the headers still going.
+    let request_line = "POST /v1/report HTTP/1.1\r\nX-Padding: ";
+    let padding = "a".repeat((8 << 10) - request_line.len());
+    let mut stream = TcpStream::connect(&listen).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(stream, "{}{}", request_line, padding).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+}
+
+#[test]
+fn leds_of_services_that_went_away_are_cleared() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let listen = free_address();
+    let dir = test_dir("agent_cleared");
+    let _server = Daemon::start_as(
+        "server",
+        &write_config(&dir, &cloud, &server_config(&listen)),
+    );
+    cloud.next_call().expect("the server didn't start");
+
+    let response = report(&listen, r#"{"healthy":"online","broken":"failed"}"#);
+    assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+    assert!(
+        wait_for(&cloud, "2:failed+alert"),
+        "the report wasn't shown"
+    );
+
+    report(&listen, r#"{"healthy":"online"}"#);
+    assert!(
+        wait_for(&cloud, "2:unknown"),
+        "the LED kept showing a service that went away"
+    );
+}
@@ -0,0 +1,360 @@
+//! A stand-in for the Particle Cloud API, and a way to run the daemon against it.
+
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const DEVICE: &str = "test_button";
+
+/// How the mock device behaves.
+pub struct MockOptions {
+    /// The `protocolVersion` variable, or `None` for firmware without it.
+    pub protocol_version: Option<i32>,
+    /// Whether the firmware has `setLeds`.
+    pub set_leds: bool,
+    /// How many function calls, from the first, return -1.
+    pub rejected_calls: usize,
+    /// How many function calls, from the first, find the device offline.
+    pub offline_calls: usize,
+}
+
+impl Default for MockOptions {
+    fn default() -> MockOptions {
+        MockOptions {
+            protocol_version: Some(6),
+            set_leds: true,
+            rejected_calls: 0,
+            offline_calls: 0,
+        }
+    }
+}
+
+/// A function call the mock device received.
+#[derive(Debug, PartialEq)]
+pub struct Call {
+    pub device: String,
+    pub function: String,
+    pub arg: String,
+}
+
+impl Call {
+    /// The `<led>:<status>` pairs a call set, sorted, whether it was batched or not.
+    pub fn pairs(&self) -> Vec<String> {
+        let mut pairs: Vec<String> = if self.function == "setLeds" {
+            self.arg.split(',').map(str::to_string).collect()
+        } else {
+            let status = match self.function.as_str() {
+                "setLed" => return vec![self.arg.clone()],
+                "setUndefined" => "unknown".to_string(),
+                function => function.trim_start_matches("set").to_lowercase(),
+            };
+            // Flags follow the LED number, but go after the status in `setLeds`.
+            let (led, flags) = self
+                .arg
+                .split_at(self.arg.find('+').unwrap_or(self.arg.len()));
+            vec![format!("{}:{}{}", led, status, flags)]
+        };
+        pairs.sort();
+        pairs
+    }
+}
+
+pub struct MockCloud {
+    pub url: String,
+    calls: Receiver<Call>,
+    events: Sender<(String, String)>,
+}
+
+impl MockCloud {
+    pub fn start(options: MockOptions) -> MockCloud {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (call_sender, calls) = mpsc::channel();
+        let (events, event_receiver) = mpsc::channel();
+
+        let options = Arc::new(options);
+        let event_receiver = Arc::new(Mutex::new(event_receiver));
+        let call_count = Arc::new(AtomicUsize::new(0));
+        let offline_count = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let options = options.clone();
+                let call_sender = call_sender.clone();
+                let event_receiver = event_receiver.clone();
+                let call_count = call_count.clone();
+                let offline_count = offline_count.clone();
+                thread::spawn(move || {
+                    handle(
+                        stream,
+                        &options,
+                        &call_sender,
+                        &event_receiver,
+                        &call_count,
+                        &offline_count,
+                    )
+                });
+            }
+        });
+
+        MockCloud { url, calls, events }
+    }
+
+    /// The next function call, if one arrives within a few seconds.
+    pub fn next_call(&self) -> Option<Call> {
+        self.calls.recv_timeout(Duration::from_secs(10)).ok()
+    }
+
+    /// Waits for the calls from now on to have set the LEDs to `pairs`, whether it took one call or
+    /// several. Returns the LEDs those calls set, as last set.
+    pub fn leds_become(&self, pairs: &[&str]) -> Vec<String> {
+        let mut leds: BTreeMap<String, String> = BTreeMap::new();
+        while let Some(call) = self.next_call() {
+            for pair in call.pairs() {
+                let led = pair.split(':').next().unwrap_or_default().to_string();
+                leds.insert(led, pair);
+            }
+            if leds.values().eq(pairs) {
+                break;
+            }
+        }
+        leds.into_values().collect()
+    }
+
+    /// Publishes a `spark/status` event, e.g. "online", on the event stream.
+    pub fn publish_status(&self, status: &str) {
+        self.publish("spark/status", status);
+    }
+
+    /// Publishes a press of `button`, from 1, on the event stream.
+    pub fn press_button(&self, button: u8) {
+        self.publish("app-status/button", &button.to_string());
+    }
+
+    /// Publishes an event from the device on the event stream.
+    pub fn publish(&self, name: &str, data: &str) {
+        self.events
+            .send((name.to_string(), data.to_string()))
+            .unwrap();
+    }
+}
+
+fn handle(
+    stream: TcpStream,
+    options: &MockOptions,
+    calls: &Sender<Call>,
+    events: &Mutex<Receiver<(String, String)>>,
+    call_count: &AtomicUsize,
+    offline_count: &AtomicUsize,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let Some((device, rest)) = path
+        .strip_prefix("/v1/devices/")
+        .and_then(|path| path.split_once('/'))
+    else {
+        return respond(stream, 404, r#"{"error":"Device not found"}"#);
+    };
+
+    match (method, rest) {
+        ("GET", "events") => stream_events(stream, events),
+        ("GET", "protocolVersion") => match options.protocol_version {
+            Some(version) => respond(stream, 200, &format!(r#"{{"result":{}}}"#, version)),
+            None => respond(stream, 404, r#"{"error":"Variable not found"}"#),
+        },
+        ("POST", function) => {
+            if function == "setLeds" && !options.set_leds {
+                return respond(stream, 404, r#"{"error":"Function setLeds not found"}"#);
+            }
+            if offline_count.fetch_add(1, Ordering::SeqCst) < options.offline_calls {
+                return respond(stream, 404, r#"{"error":"Device is offline"}"#);
+            }
+            let body = String::from_utf8(body).unwrap();
+            let arg = body
+                .split(r#""arg":""#)
+                .nth(1)
+                .and_then(|arg| arg.split('"').next())
+                .unwrap()
+                .to_string();
+            let return_value = if call_count.fetch_add(1, Ordering::SeqCst) < options.rejected_calls
+            {
+                -1
+            } else if function == "setLeds" {
+                arg.split(',').count() as i64
+            } else {
+                // setLed takes "<led>:<rrggbb>:<pattern>", the rest just the LED, either with flags.
+                arg.split([':', '+']).next().unwrap().parse().unwrap()
+            };
+            calls
+                .send(Call {
+                    device: device.to_string(),
+                    function: function.to_string(),
+                    arg,
+                })
+                .ok();
+            respond(
+                stream,
+                200,
+                &format!(
+                    r#"{{"id":"0123456789abcdef","name":"{}","connected":true,"return_value":{}}}"#,
+                    device, return_value
+                ),
+            )
+        }
+        _ => respond(stream, 404, r#"{"error":"Not found"}"#),
+    }
+}
+
+fn respond(mut stream: TcpStream, status: u16, body: &str) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+fn stream_events(mut stream: TcpStream, events: &Mutex<Receiver<(String, String)>>) {
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n:ok\n\n"
+    );
+    let _ = stream.flush();
+    let events = events.lock().unwrap();
+    while let Ok((name, data)) = events.recv() {
+        let event = format!(
+            "event: {}\ndata: {{\"data\":\"{}\",\"ttl\":60,\"published_at\":\"2026-01-01T00:00:00.000Z\",\"coreid\":\"0123456789abcdef\"}}\n\n",
+            name, data
+        );
+        if stream.write_all(event.as_bytes()).is_err() {
+            return;
+        }
+        let _ = stream.flush();
+    }
+}
+
+/// A scratch directory for one test's config and state file.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("app_status_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a config for a single device pointing at `cloud`, followed by `rest`, e.g. services.
+pub fn write_config(dir: &Path, cloud: &MockCloud, rest: &str) -> PathBuf {
+    let device = format!(
+        "[device]\nname = \"{}\"\naccess_token = \"test_token\"\n\n{}",
+        DEVICE, rest
+    );
+    write_raw_config(dir, cloud, &device)
+}
+
+/// Writes a config pointing at `cloud`, followed by `rest`, which has to list the devices.
+pub fn write_raw_config(dir: &Path, cloud: &MockCloud, rest: &str) -> PathBuf {
+    let path = dir.join("config.toml");
+    let config = format!(
+        r#"state_file = "{}"
+
+[particle]
+api_url = "{}"
+timeout = 5
+
+{}"#,
+        dir.join("led_state.toml").display(),
+        cloud.url,
+        rest
+    );
+    std::fs::write(&path, config).unwrap();
+    path
+}
+
+/// The daemon, killed when dropped.
+pub struct Daemon(Child);
+
+impl Daemon {
+    pub fn start(config: &Path) -> Daemon {
+        Daemon::spawn(None, config, None)
+    }
+
+    /// Starts the daemon with `bin` ahead of everything else on its `PATH`, e.g. to stand in for
+    /// a command it runs.
+    pub fn start_with_path(config: &Path, bin: Option<&Path>) -> Daemon {
+        Daemon::spawn(None, config, bin)
+    }
+
+    /// Starts the daemon with a subcommand, e.g. "agent".
+    pub fn start_as(subcommand: &str, config: &Path) -> Daemon {
+        Daemon::spawn(Some(subcommand), config, None)
+    }
+
+    fn spawn(subcommand: Option<&str>, config: &Path, bin: Option<&Path>) -> Daemon {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_app_status_rust"));
+        if let Some(bin) = bin {
+            let path = std::env::var("PATH").unwrap_or_default();
+            command.env("PATH", format!("{}:{}", bin.display(), path));
+        }
+        Daemon(
+            command
+                .args(subcommand)
+                .arg(config)
+                .current_dir(config.parent().unwrap())
+                .env_remove("ACCESS_TOKEN")
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap(),
+        )
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// A local address nothing listens on yet, for the daemon to listen on.
+pub fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Runs the daemon until it exits by itself, e.g. on a startup error.
+pub fn run_to_exit(config: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_app_status_rust"))
+        .arg(config)
+        .current_dir(config.parent().unwrap())
+        .env_remove("ACCESS_TOKEN")
+        .output()
+        .unwrap()
+}
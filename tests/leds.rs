@@ -0,0 +1,38 @@
+//! Runs the daemon against a mock Particle Cloud to check how LEDs are handed out.
+
+mod common;
+
+use common::{test_dir, write_config, Daemon, MockCloud, MockOptions};
+
+#[test]
+fn unpinned_services_keep_their_leds_across_restarts() {
+    let dir = test_dir("led_restart");
+    let services = |first: (&str, &str), second: (&str, &str)| {
+        [first, second]
+            .iter()
+            .map(|(name, command)| {
+                format!(
+                    "[[services]]\nname = \"{}\"\ncommand = \"{}\"\n",
+                    name, command
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let cloud = MockCloud::start(MockOptions::default());
+    let config = services(("healthy", "true"), ("broken", "false"));
+    let daemon = Daemon::start(&write_config(&dir, &cloud, &config));
+    let before = cloud.next_call().expect("no call was made").pairs();
+    assert_eq!(before, ["1:online", "2:failed+alert"]);
+    drop(daemon);
+
+    // Handed out in order, the LEDs would now swap.
+    let cloud = MockCloud::start(MockOptions::default());
+    let config = services(("broken", "false"), ("healthy", "true"));
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, &config));
+    let after = cloud
+        .next_call()
+        .expect("no call was made after the restart");
+    assert_eq!(after.pairs(), before);
+}
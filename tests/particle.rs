@@ -0,0 +1,260 @@
+//! Runs the daemon against a mock Particle Cloud.
+
+mod common;
+
+use common::{
+    run_to_exit, test_dir, write_config, write_raw_config, Daemon, MockCloud, MockOptions,
+};
+
+const TWO_SERVICES: &str = r#"
+[[services]]
+name = "healthy"
+command = "true"
+led = 1
+
+[[services]]
+name = "broken"
+command = "false"
+led = 2
+"#;
+
+#[test]
+fn startup_sets_every_led_in_one_batch() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("startup_batch");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    let call = cloud.next_call().expect("no call was made");
+    assert_eq!(call.function, "setLeds");
+    assert_eq!(call.pairs(), ["1:online", "2:failed+alert"]);
+}
+
+#[test]
+fn falls_back_to_one_call_per_led_without_set_leds() {
+    let cloud = MockCloud::start(MockOptions {
+        set_leds: false,
+        ..MockOptions::default()
+    });
+    let dir = test_dir("per_led_fallback");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    let mut pairs: Vec<String> = (0..2)
+        .flat_map(|_| cloud.next_call().expect("no call was made").pairs())
+        .collect();
+    pairs.sort();
+    assert_eq!(pairs, ["1:online", "2:failed+alert"]);
+}
+
+#[test]
+fn batching_resumes_after_the_device_was_offline() {
+    let cloud = MockCloud::start(MockOptions {
+        offline_calls: 1,
+        ..MockOptions::default()
+    });
+    let dir = test_dir("offline_batch");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    // The first batch found the device offline; the retries may come one LED at a time.
+    let mut call = cloud.next_call().expect("the update was not retried");
+    cloud.publish_status("offline");
+    cloud.publish_status("online");
+    while call.function != "setLeds" {
+        call = cloud
+            .next_call()
+            .expect("the LEDs were not set in one batch again");
+    }
+    assert_eq!(call.pairs(), ["1:online", "2:failed+alert"]);
+}
+
+#[test]
+fn rejected_updates_are_retried() {
+    let cloud = MockCloud::start(MockOptions {
+        rejected_calls: 1,
+        ..MockOptions::default()
+    });
+    let dir = test_dir("retry");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    let first = cloud.next_call().expect("no call was made");
+    // Each LED backs off on its own, so the retries may come separately.
+    let mut retried: Vec<String> = Vec::new();
+    while retried.len() < first.pairs().len() {
+        retried.extend(
+            cloud
+                .next_call()
+                .expect("the update was not retried")
+                .pairs(),
+        );
+    }
+    retried.sort();
+    assert_eq!(first.pairs(), retried);
+}
+
+#[test]
+fn device_coming_online_resyncs_every_led() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("resync_on_online");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    cloud.next_call().expect("no call was made");
+    cloud.publish_status("offline");
+    cloud.publish_status("online");
+
+    let call = cloud.next_call().expect("the LEDs were not resynced");
+    assert_eq!(call.pairs(), ["1:online", "2:failed+alert"]);
+}
+
+#[test]
+fn incompatible_firmware_is_refused() {
+    let cloud = MockCloud::start(MockOptions {
+        protocol_version: Some(1),
+        ..MockOptions::default()
+    });
+    let dir = test_dir("incompatible");
+    let output = run_to_exit(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("protocol version 1"), "{}", stderr);
+}
+
+#[test]
+fn firmware_without_a_version_is_still_driven() {
+    let cloud = MockCloud::start(MockOptions {
+        protocol_version: None,
+        ..MockOptions::default()
+    });
+    let dir = test_dir("unversioned");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    // Without batches, styles or flags, and with failures shown as offline.
+    let mut calls: Vec<(String, String)> = (0..2)
+        .map(|_| {
+            let call = cloud.next_call().expect("no call was made");
+            (call.function, call.arg)
+        })
+        .collect();
+    calls.sort_by(|a, b| a.1.cmp(&b.1));
+    assert_eq!(
+        calls,
+        [
+            ("setOnline".to_string(), "1".to_string()),
+            ("setOffline".to_string(), "2".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn styled_statuses_are_sent_with_their_color_and_pattern() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("styles");
+    let config = format!(
+        "{}\n[styles]\nfailed = {{ color = \"#ff0000\", pattern = \"blink\" }}\n",
+        TWO_SERVICES
+    );
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, &config));
+
+    let call = cloud.next_call().expect("no call was made");
+    assert_eq!(call.pairs(), ["1:online", "2:ff0000:blink+alert"]);
+}
+
+#[test]
+fn status_changes_flash_the_led() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("flash");
+    let flag = dir.join("healthy");
+    let services = format!(
+        "[[services]]\nname = \"toggled\"\ncommand = \"test -f {}\"\nled = 1\npoll_interval = 1\n",
+        flag.display()
+    );
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, &services));
+
+    let call = cloud.next_call().expect("no call was made");
+    assert_eq!(call.pairs(), ["1:failed+alert"]);
+    std::fs::write(&flag, "").unwrap();
+
+    let call = cloud.next_call().expect("the change was not sent");
+    assert_eq!(call.pairs(), ["1:online+flash"]);
+}
+
+#[test]
+fn acknowledging_stops_the_alert() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("acknowledge");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    cloud.next_call().expect("no call was made");
+    cloud.press_button(1);
+
+    let call = cloud.next_call().expect("the alert was not cleared");
+    assert_eq!(call.pairs(), ["2:failed"]);
+}
+
+#[test]
+fn muting_stops_the_alert() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("mute");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    cloud.next_call().expect("no call was made");
+    cloud.press_button(3);
+    let call = cloud.next_call().expect("the alert was not muted");
+    assert_eq!(call.pairs(), ["2:failed"]);
+
+    cloud.press_button(3);
+    let call = cloud.next_call().expect("the alert was not unmuted");
+    assert_eq!(call.pairs(), ["2:failed+alert"]);
+}
+
+#[test]
+fn detail_steps_through_the_leds() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("detail");
+    let _daemon = Daemon::start(&write_config(&dir, &cloud, TWO_SERVICES));
+
+    cloud.next_call().expect("no call was made");
+    cloud.press_button(2);
+    let call = cloud.next_call().expect("the first LED was not flashed");
+    assert_eq!(call.pairs(), ["1:online+flash"]);
+
+    cloud.press_button(2);
+    let call = cloud.next_call().expect("the second LED was not flashed");
+    assert_eq!(call.pairs(), ["2:failed+flash+alert"]);
+}
+
+#[test]
+fn each_device_shows_its_own_services() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("devices");
+    let config = r#"
+[[devices]]
+name = "rack1"
+access_token = "test_token"
+
+[[devices]]
+name = "rack2"
+access_token = "test_token"
+
+[[services]]
+name = "shared"
+command = "true"
+
+[[services]]
+name = "rack1_only"
+command = "false"
+devices = ["rack1"]
+"#;
+    let _daemon = Daemon::start(&write_raw_config(&dir, &cloud, config));
+
+    let mut calls: Vec<(String, Vec<String>)> = (0..2)
+        .map(|_| cloud.next_call().expect("no call was made"))
+        .map(|call| (call.device.clone(), call.pairs()))
+        .collect();
+    calls.sort();
+    assert_eq!(calls[0].0, "rack1");
+    assert_eq!(calls[0].1.len(), 2);
+    assert_eq!(
+        calls[1],
+        ("rack2".to_string(), vec!["1:online".to_string()])
+    );
+}
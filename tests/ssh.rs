@@ -0,0 +1,131 @@
+//! Checks units on other hosts, with a script standing in for `ssh`.
+
+mod common;
+
+use common::{test_dir, write_config, Daemon, MockCloud, MockOptions};
+use std::os::unix::fs::PermissionsExt;
+
+/// Answers like `systemctl show` on `up` and `down`, and fails to connect to anything else.
+const FAKE_SSH: &str = r#"#!/bin/sh
+for arg; do
+    case "$arg" in
+        up) printf 'LoadState=loaded\nActiveState=active\nSubState=running\nResult=success\n'; exit 0 ;;
+        down) printf 'LoadState=loaded\nActiveState=failed\nSubState=failed\nResult=exit-code\n'; exit 0 ;;
+    esac
+done
+echo "ssh: connect to host: Connection refused" >&2
+exit 255
+"#;
+
+#[test]
+fn unreachable_hosts_are_told_apart_from_failed_units() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("ssh");
+    let bin = dir.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(bin.join("ssh"), FAKE_SSH).unwrap();
+    std::fs::set_permissions(bin.join("ssh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let config = r#"
+[hosts.web1]
+address = "up"
+
+[hosts.web2]
+address = "down"
+
+[hosts.web3]
+address = "gone"
+
+[[services]]
+unit = "nginx.service"
+host = "web1"
+led = 1
+
+[[services]]
+unit = "nginx.service"
+host = "web2"
+led = 2
+
+[[services]]
+unit = "nginx.service"
+host = "web3"
+led = 3
+"#;
+    let _daemon = Daemon::start_with_path(&write_config(&dir, &cloud, config), Some(&bin));
+
+    // The units are unknown until their first check.
+    let call = cloud.next_call().expect("no call was made");
+    assert_eq!(call.pairs(), ["1:unknown", "2:unknown", "3:unknown"]);
+    assert_eq!(
+        cloud.leds_become(&["1:online", "2:failed+alert", "3:unreachable"]),
+        ["1:online", "2:failed+alert", "3:unreachable"]
+    );
+}
+
+#[test]
+fn a_host_that_times_out_is_only_waited_for_once_per_poll() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("ssh_timeout");
+    let bin = dir.join("bin");
+    let calls = dir.join("calls");
+    std::fs::create_dir(&bin).unwrap();
+    let fake_ssh = format!("#!/bin/sh\necho call >> '{}'\nsleep 5\n", calls.display());
+    std::fs::write(bin.join("ssh"), fake_ssh).unwrap();
+    std::fs::set_permissions(bin.join("ssh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let config = r#"
+[hosts.web1]
+address = "slow"
+timeout = 1
+
+[[services]]
+unit = "nginx.service"
+host = "web1"
+led = 1
+poll_interval = 60
+
+[[services]]
+unit = "postgresql.service"
+host = "web1"
+led = 2
+poll_interval = 60
+"#;
+    let _daemon = Daemon::start_with_path(&write_config(&dir, &cloud, config), Some(&bin));
+
+    assert_eq!(
+        cloud.leds_become(&["1:unreachable", "2:unreachable"]),
+        ["1:unreachable", "2:unreachable"]
+    );
+    assert_eq!(std::fs::read_to_string(&calls).unwrap().lines().count(), 1);
+}
+
+#[test]
+fn a_slow_host_doesnt_hold_up_other_services() {
+    let cloud = MockCloud::start(MockOptions::default());
+    let dir = test_dir("ssh_slow");
+    let bin = dir.join("bin");
+    std::fs::create_dir(&bin).unwrap();
+    std::fs::write(bin.join("ssh"), "#!/bin/sh\nsleep 10\n").unwrap();
+    std::fs::set_permissions(bin.join("ssh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let config = r#"
+[hosts.web1]
+address = "slow"
+timeout = 3
+
+[[services]]
+unit = "nginx.service"
+host = "web1"
+led = 1
+
+[[services]]
+name = "local"
+command = "true"
+led = 2
+"#;
+    let _daemon = Daemon::start_with_path(&write_config(&dir, &cloud, config), Some(&bin));
+
+    let call = cloud.next_call().expect("no call was made");
+    assert_eq!(call.pairs(), ["1:unknown", "2:online"]);
+    assert_eq!(cloud.leds_become(&["1:unreachable"]), ["1:unreachable"]);
+}